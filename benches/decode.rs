@@ -0,0 +1,59 @@
+//! Decoding cost and frame size of pointer events, JSON against binary.
+//!
+//! Both go through what the server runs on each frame, `protocol::parse`
+//! and `protocol::parse_binary`, capability check included.
+//!
+//! Run with `cargo bench --bench decode`; the throughput column is bytes
+//! per frame, so it also shows how much smaller binary frames are.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// The server is a binary crate, so borrow the modules the decoders need
+#[allow(dead_code, unused_imports)]
+#[path = "../src/binary.rs"]
+mod binary;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/events.rs"]
+mod events;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/keys.rs"]
+mod keys;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/protocol.rs"]
+mod protocol;
+
+use events::Device;
+use protocol::{Capabilities, Encoding};
+
+fn delta_frame(opcode: u8, dx: f32, dy: f32) -> Vec<u8> {
+    let mut frame = vec![opcode];
+    frame.extend_from_slice(&dx.to_le_bytes());
+    frame.extend_from_slice(&dy.to_le_bytes());
+    frame
+}
+
+fn decode(c: &mut Criterion) {
+    // What the trackpad page sends: sub-pixel deltas scaled by the sensitivity
+    let cases = [
+        ("move", r#"{"type":"move","dx":3.4500000000000002,"dy":-1.2}"#.to_string(), delta_frame(binary::MOVE, 3.45, -1.2)),
+        ("scroll", r#"{"type":"scroll","dx":0,"dy":-14.6}"#.to_string(), delta_frame(binary::SCROLL, 0.0, -14.6)),
+        ("click", r#"{"type":"click","button":"left"}"#.to_string(), vec![binary::CLICK, 0]),
+    ];
+    let capabilities = Capabilities::new(vec![Device::Pointer, Device::Keyboard], false, vec!["flat".to_string()]);
+
+    for (name, json, frame) in &cases {
+        let mut group = c.benchmark_group(*name);
+        group.throughput(Throughput::Bytes(json.len() as u64));
+        group.bench_with_input(BenchmarkId::new("json", json.len()), json, |b, json| {
+            b.iter(|| protocol::parse(black_box(json), &capabilities).unwrap())
+        });
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_with_input(BenchmarkId::new("binary", frame.len()), frame, |b, frame| {
+            b.iter(|| protocol::parse_binary(black_box(frame), Encoding::Binary, &capabilities).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
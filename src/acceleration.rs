@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::events::{MotionKind, TrackpadEvent};
+
+// Pointer acceleration: moves are scaled by a gain that depends on how
+// fast the pointer is going, so slow movements stay precise and fast
+// flicks cross the screen. Speeds are in pixels per millisecond, as the
+// client sent them.
+
+/// Moves this much closer together aren't timed on their own; their
+/// distance counts towards the next one.
+const MIN_INTERVAL_MS: f64 = 1.0;
+
+/// A longer pause starts a new movement from rest.
+const IDLE_MS: f64 = 100.0;
+
+/// Weight of the newest speed against the ones before it.
+const SMOOTHING: f64 = 0.5;
+
+/// How speed turns into gain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "curve", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Curve {
+    /// `gain + slope * speed`, up to `max_gain`. A slope of 0 leaves the
+    /// deltas as they came, times `gain`.
+    Linear { gain: f64, slope: f64, max_gain: f64 },
+    /// Like libinput's adaptive profile: slow movements are scaled down
+    /// from 1 to `min_gain` for precision, and above `threshold` the gain
+    /// climbs by `incline` per pixel/ms up to `max_gain`.
+    Adaptive {
+        threshold: f64,
+        incline: f64,
+        min_gain: f64,
+        max_gain: f64,
+    },
+    /// `[speed, gain]` points, joined by straight lines and flat past the
+    /// first and last.
+    Piecewise { points: Vec<[f64; 2]> },
+}
+
+impl Curve {
+    /// Leaves every move as it is.
+    pub const FLAT: Curve = Curve::Linear { gain: 1.0, slope: 0.0, max_gain: 1.0 };
+
+    pub fn gain(&self, speed: f64) -> f64 {
+        match self {
+            Curve::Linear { gain, slope, max_gain } => (gain + slope * speed).min(*max_gain),
+            Curve::Adaptive { threshold, incline, min_gain, max_gain } => {
+                if speed < *threshold {
+                    min_gain + (1.0 - min_gain) * speed / threshold
+                } else {
+                    (1.0 + incline * (speed - threshold)).min(*max_gain)
+                }
+            }
+            Curve::Piecewise { points } => {
+                let Some(next) = points.iter().position(|[s, _]| *s > speed) else {
+                    return points.last().map_or(1.0, |[_, gain]| *gain);
+                };
+                if next == 0 {
+                    return points[0][1];
+                }
+                let [s0, g0] = points[next - 1];
+                let [s1, g1] = points[next];
+                g0 + (g1 - g0) * (speed - s0) / (s1 - s0)
+            }
+        }
+    }
+
+    /// Check the curve gives a positive, finite gain at every speed.
+    pub fn validate(&self) -> Result<(), String> {
+        let numbers: Vec<f64> = match self {
+            Curve::Linear { gain, slope, max_gain } => {
+                if *gain <= 0.0 || *slope < 0.0 || max_gain < gain {
+                    return Err("linear curves need gain > 0, slope >= 0 and max_gain >= gain".to_string());
+                }
+                vec![*gain, *slope, *max_gain]
+            }
+            Curve::Adaptive { threshold, incline, min_gain, max_gain } => {
+                if *threshold <= 0.0 || *incline < 0.0 || !(*min_gain > 0.0 && *min_gain <= 1.0) || *max_gain < 1.0 {
+                    return Err(
+                        "adaptive curves need threshold > 0, incline >= 0, 0 < min_gain <= 1 and max_gain >= 1".to_string(),
+                    );
+                }
+                vec![*threshold, *incline, *min_gain, *max_gain]
+            }
+            Curve::Piecewise { points } => {
+                if points.is_empty() {
+                    return Err("piecewise curves need at least one point".to_string());
+                }
+                if points.windows(2).any(|pair| pair[1][0] <= pair[0][0]) {
+                    return Err("piecewise curve speeds must increase".to_string());
+                }
+                if points.iter().any(|[_, gain]| *gain <= 0.0) {
+                    return Err("piecewise curve gains must be > 0".to_string());
+                }
+                points.iter().flatten().copied().collect()
+            }
+        };
+        if numbers.iter().all(|n| n.is_finite()) {
+            Ok(())
+        } else {
+            Err("curve settings must be finite".to_string())
+        }
+    }
+}
+
+/// The profiles every server has; configured ones of the same name win.
+pub fn builtin_profiles() -> BTreeMap<String, Curve> {
+    BTreeMap::from([
+        ("flat".to_string(), Curve::FLAT),
+        ("linear".to_string(), Curve::Linear { gain: 1.0, slope: 0.5, max_gain: 3.0 }),
+        (
+            "adaptive".to_string(),
+            Curve::Adaptive { threshold: 0.5, incline: 1.2, min_gain: 0.6, max_gain: 3.0 },
+        ),
+    ])
+}
+
+/// When a move happened: by the client's clock if it said, and always by
+/// ours.
+#[derive(Debug, Clone, Copy)]
+struct Stamp {
+    client: Option<f64>,
+    server: f64,
+}
+
+/// Accelerates one connection's moves along a curve.
+#[derive(Debug)]
+pub struct Accelerator {
+    curve: Curve,
+    last: Option<Stamp>,
+    /// Distance of moves since `last` too close to it to time.
+    pending: f64,
+    /// Smoothed speed at `last`.
+    speed: f64,
+}
+
+impl Accelerator {
+    pub fn new(curve: Curve) -> Self {
+        Self { curve, last: None, pending: 0.0, speed: 0.0 }
+    }
+
+    /// Scale the moves in `event`, which arrived `now` milliseconds into
+    /// the connection. Moves without a client timestamp are timed by when
+    /// they arrived.
+    pub fn accelerate(&mut self, event: &mut TrackpadEvent, now: f64) {
+        match event {
+            TrackpadEvent::Move { dx, dy, t } => {
+                let gain = self.gain(dx.hypot(*dy), Stamp { client: *t, server: now });
+                *dx *= gain;
+                *dy *= gain;
+            }
+            TrackpadEvent::Batch { events } => {
+                for delta in events.iter_mut().filter(|d| d.kind == MotionKind::Move) {
+                    let gain = self.gain(delta.dx.hypot(delta.dy), Stamp { client: delta.t, server: now });
+                    delta.dx *= gain;
+                    delta.dy *= gain;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Gain for moving `distance` at `stamp`.
+    fn gain(&mut self, distance: f64, stamp: Stamp) -> f64 {
+        // Client timestamps are more even, but only comparable to each other
+        let elapsed = self.last.map(|last| match (last.client, stamp.client) {
+            (Some(before), Some(now)) => now - before,
+            _ => stamp.server - last.server,
+        });
+        match elapsed {
+            Some(elapsed) if (0.0..MIN_INTERVAL_MS).contains(&elapsed) => {
+                self.pending += distance;
+            }
+            Some(elapsed) if (MIN_INTERVAL_MS..=IDLE_MS).contains(&elapsed) => {
+                let speed = (self.pending + distance) / elapsed;
+                self.speed = SMOOTHING * speed + (1.0 - SMOOTHING) * self.speed;
+                self.pending = 0.0;
+                self.last = Some(stamp);
+            }
+            // The first move, one after a pause, or a clock that went back
+            _ => {
+                self.speed = 0.0;
+                self.pending = 0.0;
+                self.last = Some(stamp);
+            }
+        }
+        self.curve.gain(self.speed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn linear_gain_grows_with_speed_up_to_the_cap() {
+        let curve = Curve::Linear { gain: 1.0, slope: 0.5, max_gain: 3.0 };
+        assert_close(curve.gain(0.0), 1.0);
+        assert_close(curve.gain(2.0), 2.0);
+        assert_close(curve.gain(10.0), 3.0);
+
+        for speed in [0.0, 1.0, 100.0] {
+            assert_close(Curve::FLAT.gain(speed), 1.0);
+        }
+    }
+
+    #[test]
+    fn adaptive_slows_precise_moves_and_speeds_up_fast_ones() {
+        let curve = Curve::Adaptive { threshold: 0.5, incline: 1.2, min_gain: 0.6, max_gain: 3.0 };
+        assert_close(curve.gain(0.0), 0.6);
+        assert_close(curve.gain(0.25), 0.8);
+        assert_close(curve.gain(0.5), 1.0);
+        assert_close(curve.gain(1.5), 2.2);
+        assert_close(curve.gain(5.0), 3.0);
+
+        // Never drops as the pointer speeds up
+        let gains: Vec<f64> = (0..100).map(|i| curve.gain(i as f64 * 0.05)).collect();
+        assert!(gains.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    fn piecewise_interpolates_between_points() {
+        let curve = Curve::Piecewise { points: vec![[0.5, 1.0], [1.0, 2.0], [3.0, 2.5]] };
+        assert_close(curve.gain(0.0), 1.0);
+        assert_close(curve.gain(0.75), 1.5);
+        assert_close(curve.gain(1.0), 2.0);
+        assert_close(curve.gain(2.0), 2.25);
+        assert_close(curve.gain(10.0), 2.5);
+    }
+
+    #[test]
+    fn rejects_curves_without_a_sensible_gain() {
+        for curve in builtin_profiles().values() {
+            assert_eq!(curve.validate(), Ok(()));
+        }
+        assert!(Curve::Linear { gain: 0.0, slope: 1.0, max_gain: 2.0 }.validate().is_err());
+        assert!(Curve::Linear { gain: 1.0, slope: f64::NAN, max_gain: 2.0 }.validate().is_err());
+        assert!(Curve::Adaptive { threshold: 0.0, incline: 1.0, min_gain: 0.5, max_gain: 2.0 }.validate().is_err());
+        assert!(Curve::Piecewise { points: vec![] }.validate().is_err());
+        assert!(Curve::Piecewise { points: vec![[1.0, 1.0], [1.0, 2.0]] }.validate().is_err());
+        assert!(Curve::Piecewise { points: vec![[1.0, -1.0]] }.validate().is_err());
+    }
+
+    #[test]
+    fn curves_parse_from_toml() {
+        let curve: Curve = toml::from_str("curve = \"piecewise\"\npoints = [[0, 1], [2, 3]]\n").unwrap();
+        assert_eq!(curve, Curve::Piecewise { points: vec![[0.0, 1.0], [2.0, 3.0]] });
+        assert!(toml::from_str::<Curve>("curve = \"linear\"\ngain = 1.0\n").is_err());
+    }
+
+    #[test]
+    fn speed_comes_from_the_timestamps() {
+        let mut accelerator = Accelerator::new(Curve::Linear { gain: 1.0, slope: 1.0, max_gain: 10.0 });
+        let mut moved = |dx: f64, t: Option<f64>, now: f64| {
+            let mut event = TrackpadEvent::Move { dx, dy: 0.0, t };
+            accelerator.accelerate(&mut event, now);
+            match event {
+                TrackpadEvent::Move { dx, .. } => dx,
+                _ => unreachable!(),
+            }
+        };
+
+        // Starting from rest
+        assert_close(moved(4.0, Some(1000.0), 0.0), 4.0);
+        // 4 px in 2 ms by the client's clock, whenever it arrives
+        assert_close(moved(4.0, Some(1002.0), 50.0), 4.0 * (1.0 + 1.0));
+        // Then 8 px in 2 ms, smoothed with the 1 px/ms before
+        assert_close(moved(8.0, Some(1004.0), 51.0), 8.0 * (1.0 + 2.5));
+        // Without a client timestamp, 2 px/ms by ours
+        assert_close(moved(20.0, None, 61.0), 20.0 * (1.0 + 2.25));
+        // After a pause it starts over
+        assert_close(moved(4.0, None, 500.0), 4.0);
+    }
+
+    #[test]
+    fn batched_moves_share_a_timestamp() {
+        let mut accelerator = Accelerator::new(Curve::Linear { gain: 1.0, slope: 1.0, max_gain: 10.0 });
+        let delta = |kind, dx, t| crate::events::MotionDelta { kind, dx, dy: 0.0, t: Some(t) };
+        let mut batch = TrackpadEvent::Batch {
+            events: vec![
+                delta(MotionKind::Move, 2.0, 0.0),
+                delta(MotionKind::Move, 3.0, 5.0),
+                delta(MotionKind::Move, 7.0, 5.0),
+                delta(MotionKind::Scroll, 10.0, 6.0),
+            ],
+        };
+        accelerator.accelerate(&mut batch, 0.0);
+
+        let TrackpadEvent::Batch { events } = batch else { unreachable!() };
+        // The move sharing a timestamp keeps the gain before it
+        for (delta, expected) in events.iter().zip([2.0, 3.0 * 1.3, 7.0 * 1.3, 10.0]) {
+            assert_close(delta.dx, expected);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+use warp::Rejection;
+
+use crate::deliver;
+use crate::downloads::{self, STREAM_CHUNK_SIZE};
+use crate::files::FileStorage;
+
+const LOCAL_HEADER_LEN: u64 = 30;
+const DATA_DESCRIPTOR_LEN: u64 = 16;
+const CENTRAL_HEADER_LEN: u64 = 46;
+const END_OF_CENTRAL_DIRECTORY_LEN: u64 = 22;
+
+/// Sizes and CRCs follow the data, and names are UTF-8.
+const FLAGS: u16 = 0x0808;
+
+/// Version 2.0: stored entries with data descriptors, no zip64.
+const VERSION: u16 = 20;
+
+/// Without zip64 every offset in the archive has to fit in 32 bits.
+const MAX_ARCHIVE_LEN: u64 = u32::MAX as u64;
+
+#[derive(Debug, Deserialize)]
+pub struct ZipQuery {
+    /// Comma-separated ids of the files to put in the archive.
+    pub ids: String,
+}
+
+/// A file to put in an archive, stored as is.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: u64,
+}
+
+/// What goes in the central directory about an entry once it's written.
+struct Written {
+    offset: u32,
+    crc: u32,
+}
+
+/// Exact length of the archive of `entries`; known before anything is
+/// read since nothing is compressed.
+pub fn archive_len(entries: &[ZipEntry]) -> u64 {
+    let files: u64 = entries
+        .iter()
+        .map(|e| LOCAL_HEADER_LEN + DATA_DESCRIPTOR_LEN + CENTRAL_HEADER_LEN + 2 * e.name.len() as u64 + e.size)
+        .sum();
+    files + END_OF_CENTRAL_DIRECTORY_LEN
+}
+
+/// `names` made unique by numbering repeats like `photo (1).jpg`.
+fn unique_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut taken = HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let unique = (0..)
+                .map(|n| deliver::numbered(name, n))
+                .find(|candidate| !taken.contains(candidate))
+                .unwrap();
+            taken.insert(unique.clone());
+            unique
+        })
+        .collect()
+}
+
+/// MS-DOS time and date of a Unix timestamp, in UTC and clamped to the
+/// 1980-2107 the format can hold.
+fn dos_datetime(secs: u64) -> (u16, u16) {
+    let Ok(t) = time::OffsetDateTime::from_unix_timestamp(secs as i64) else {
+        return (0, 0x21);
+    };
+    if t.year() < 1980 {
+        return (0, 0x21);
+    }
+    let time = ((t.hour() as u16) << 11) | ((t.minute() as u16) << 5) | (t.second() as u16 / 2);
+    let date = (((t.year() - 1980).min(127) as u16) << 9) | ((t.month() as u16) << 5) | t.day() as u16;
+    (time, date)
+}
+
+/// Write a stored (uncompressed) zip of `entries` to `out`, reading each
+/// file only once as it goes. Fails if a file is shorter than its `size`.
+pub async fn write_zip<W: AsyncWrite + Unpin>(entries: &[ZipEntry], out: &mut W) -> std::io::Result<()> {
+    let mut written = Vec::with_capacity(entries.len());
+    let mut offset = 0u64;
+
+    for entry in entries {
+        let (time, date) = dos_datetime(entry.modified);
+        let mut header = Vec::with_capacity(LOCAL_HEADER_LEN as usize + entry.name.len());
+        header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // stored
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        // CRC and sizes are in the data descriptor
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(entry.name.as_bytes());
+        out.write_all(&header).await?;
+
+        let file = tokio::fs::File::open(&entry.path).await?;
+        let crc = copy_with_crc(file.take(entry.size), entry.size, out).await?;
+
+        let mut descriptor = Vec::with_capacity(DATA_DESCRIPTOR_LEN as usize);
+        descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+        descriptor.extend_from_slice(&crc.to_le_bytes());
+        descriptor.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        descriptor.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        out.write_all(&descriptor).await?;
+
+        written.push(Written { offset: offset as u32, crc });
+        offset += LOCAL_HEADER_LEN + entry.name.len() as u64 + entry.size + DATA_DESCRIPTOR_LEN;
+    }
+
+    let mut directory = Vec::new();
+    for (entry, written) in entries.iter().zip(&written) {
+        let (time, date) = dos_datetime(entry.modified);
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // Made by Unix, so the permissions below are used
+        directory.extend_from_slice(&((3 << 8) | VERSION).to_le_bytes());
+        directory.extend_from_slice(&VERSION.to_le_bytes());
+        directory.extend_from_slice(&FLAGS.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes());
+        directory.extend_from_slice(&time.to_le_bytes());
+        directory.extend_from_slice(&date.to_le_bytes());
+        directory.extend_from_slice(&written.crc.to_le_bytes());
+        directory.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        directory.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        // Extra field, comment, disk number, internal attributes
+        directory.extend_from_slice(&[0; 8]);
+        directory.extend_from_slice(&(0o100644u32 << 16).to_le_bytes());
+        directory.extend_from_slice(&written.offset.to_le_bytes());
+        directory.extend_from_slice(entry.name.as_bytes());
+    }
+
+    let count = entries.len() as u16;
+    let mut end = Vec::with_capacity(END_OF_CENTRAL_DIRECTORY_LEN as usize);
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]); // this disk, the directory's disk
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    end.extend_from_slice(&(offset as u32).to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    out.write_all(&directory).await?;
+    out.write_all(&end).await?;
+    out.flush().await
+}
+
+/// Copy exactly `len` bytes from `input` to `out`, returning their CRC-32.
+async fn copy_with_crc<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut input: R,
+    len: u64,
+    out: &mut W,
+) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; STREAM_CHUNK_SIZE];
+    let mut copied = 0u64;
+    loop {
+        let n = input.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n]).await?;
+        copied += n as u64;
+    }
+    if copied != len {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "file changed while zipping"));
+    }
+    Ok(hasher.finalize())
+}
+
+pub async fn handle_zip(query: ZipQuery, storage: FileStorage) -> Result<Response<Body>, Rejection> {
+    let ids: Vec<&str> = query.ids.split(',').map(str::trim).filter(|id| !id.is_empty()).collect();
+    if ids.is_empty() {
+        return Ok(error_response("No files selected", StatusCode::BAD_REQUEST));
+    }
+    let mut files = Vec::with_capacity(ids.len());
+    for id in ids {
+        let Some(info) = storage.get(id) else {
+            eprintln!("❌ File ID not found in storage: {}", id);
+            return Err(warp::reject::not_found());
+        };
+        files.push(info);
+    }
+
+    let names = unique_names(files.iter().map(|f| f.filename.as_str()));
+    let entries: Vec<ZipEntry> = files
+        .iter()
+        .zip(names)
+        .map(|(info, name)| ZipEntry {
+            name,
+            path: storage.contents_path(info),
+            size: info.size,
+            modified: info.uploaded_at,
+        })
+        .collect();
+    let len = archive_len(&entries);
+    if len > MAX_ARCHIVE_LEN || entries.len() > u16::MAX as usize {
+        return Ok(error_response("Selection too large to zip", StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    println!("📦 Zip download: {} files ({} bytes)", entries.len(), len);
+
+    // Written into a pipe as the client reads, so only a chunk is ever held
+    let (mut writer, reader) = tokio::io::duplex(STREAM_CHUNK_SIZE);
+    tokio::spawn(async move {
+        // The client sees the body end short of its Content-Length
+        if let Err(e) = write_zip(&entries, &mut writer).await {
+            eprintln!("❌ Error writing zip: {}", e);
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_DISPOSITION, downloads::content_disposition("attachment", "files.zip"))
+        .header(header::CONTENT_LENGTH, len)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(Body::wrap_stream(ReaderStream::with_capacity(reader, STREAM_CHUNK_SIZE)))
+        .unwrap())
+}
+
+fn error_response(message: &str, status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!({"error": message}).to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::temp_dir;
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn numbers_repeated_names() {
+        assert_eq!(
+            unique_names(["photo.jpg", "notes.txt", "photo.jpg", "photo.jpg"]),
+            ["photo.jpg", "notes.txt", "photo (1).jpg", "photo (2).jpg"]
+        );
+    }
+
+    #[test]
+    fn converts_to_dos_time() {
+        // 2024-02-29 13:45:30 UTC
+        assert_eq!(dos_datetime(1709214330), ((13 << 11) | (45 << 5) | 15, (44 << 9) | (2 << 5) | 29));
+        assert_eq!(dos_datetime(0), (0, 0x21));
+    }
+
+    #[tokio::test]
+    async fn writes_a_readable_archive_of_the_promised_length() {
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), b"hello").unwrap();
+        std::fs::write(dir.join("b"), b"").unwrap();
+        let entries = vec![
+            ZipEntry { name: "hello.txt".to_string(), path: dir.join("a"), size: 5, modified: 1709214330 },
+            ZipEntry { name: "caf\u{e9}.txt".to_string(), path: dir.join("b"), size: 0, modified: 1709214330 },
+        ];
+
+        let mut zip = Vec::new();
+        write_zip(&entries, &mut zip).await.unwrap();
+        assert_eq!(zip.len() as u64, archive_len(&entries));
+
+        // The end record points at a directory listing both entries
+        let end = zip.len() - END_OF_CENTRAL_DIRECTORY_LEN as usize;
+        assert_eq!(u32_at(&zip, end), 0x06054b50);
+        assert_eq!(u16_at(&zip, end + 10), 2);
+        let directory = u32_at(&zip, end + 16) as usize;
+        assert_eq!(u32_at(&zip, directory), 0x02014b50);
+        assert_eq!(u32_at(&zip, directory + 16), crc32fast::hash(b"hello"));
+        assert_eq!(u32_at(&zip, directory + 24), 5);
+        assert_eq!(&zip[directory + 46..directory + 55], b"hello.txt");
+
+        // Contents follow the local header as is
+        assert_eq!(u32_at(&zip, 0), 0x04034b50);
+        assert_eq!(&zip[30 + 9..30 + 9 + 5], b"hello");
+        let second = u32_at(&zip, directory + 46 + 9 + 42) as usize;
+        assert_eq!(u32_at(&zip, second), 0x04034b50);
+        assert_eq!(&zip[second + 30..second + 30 + 5], "caf\u{e9}".as_bytes());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn fails_when_a_file_shrank() {
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), b"hel").unwrap();
+        let entries = [ZipEntry { name: "a".to_string(), path: dir.join("a"), size: 5, modified: 0 }];
+
+        let err = write_zip(&entries, &mut Vec::new()).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,91 @@
+use rust_embed::RustEmbed;
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+use warp::path::Tail;
+use warp::{Filter, Rejection};
+
+/// The web UI, compiled into the binary.
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct Assets;
+
+/// Serve the web UI: `/` is `index.html`, everything else is looked up by
+/// path (with or without a leading `/static/`).
+///
+/// With an override directory, files found there win over the embedded
+/// copies, so the UI can be edited without rebuilding.
+pub fn routes(
+    override_dir: Option<PathBuf>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let override_dir = override_dir.map(Arc::new);
+    warp::get()
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::any().map(move || override_dir.clone()))
+        .and_then(serve_asset)
+}
+
+async fn serve_asset(
+    tail: Tail,
+    if_none_match: Option<String>,
+    override_dir: Option<Arc<PathBuf>>,
+) -> Result<Response<Body>, Rejection> {
+    let path = tail.as_str();
+    let path = path.strip_prefix("static/").unwrap_or(path);
+    let path = if path.is_empty() { "index.html" } else { path };
+
+    let (data, etag) = match load(path, override_dir.as_deref()).await {
+        Some(found) => found,
+        None => return Err(warp::reject::not_found()),
+    };
+
+    let etag = format!("\"{}\"", etag);
+    let builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, "no-cache");
+
+    // Browsers revalidate every time; unchanged files cost a 304
+    if if_none_match.is_some_and(|tags| tags.split(',').any(|t| t.trim().trim_start_matches("W/") == etag)) {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    Ok(builder
+        .header(header::CONTENT_TYPE, mime.as_ref())
+        .body(Body::from(data))
+        .unwrap())
+}
+
+/// File contents and a hex SHA-256 to use as the ETag.
+async fn load(path: &str, override_dir: Option<&PathBuf>) -> Option<(Vec<u8>, String)> {
+    if let Some(dir) = override_dir {
+        if is_safe_path(path) {
+            if let Ok(data) = tokio::fs::read(dir.join(path)).await {
+                let etag = format!("{:x}", Sha256::digest(&data));
+                return Some((data, etag));
+            }
+        }
+    }
+
+    let file = Assets::get(path)?;
+    let etag = file
+        .metadata
+        .sha256_hash()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Some((file.data.into_owned(), etag))
+}
+
+/// Only plain relative paths, so the override directory can't be escaped.
+fn is_safe_path(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
@@ -0,0 +1,514 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Cookie the pairing page stores the device token in, so WebSocket upgrades
+/// and download links carry it without any JavaScript.
+pub const TOKEN_COOKIE: &str = "trackpad_token";
+
+/// Wrong PINs allowed before the PIN is replaced.
+const MAX_PIN_ATTEMPTS: u32 = 5;
+
+/// Wrong PINs in a row allowed before pairing is paused.
+const FREE_PIN_ATTEMPTS: u32 = 3;
+
+/// The first pause; each further wrong PIN doubles it.
+const PIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest pause, which still allows only a dozen guesses an hour.
+const MAX_PIN_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedDevice {
+    pub id: String,
+    pub name: String,
+    /// SHA-256 of the token; the token itself is only ever sent to the device.
+    token_hash: String,
+    pub paired_at: u64,
+}
+
+struct Pairing {
+    pin: String,
+    failed_attempts: u32,
+    /// Wrong PINs since the last pairing, across PIN changes.
+    failures: u32,
+    /// No PINs are checked before then, right or wrong.
+    locked_until: Option<Instant>,
+}
+
+/// What came of a pairing attempt.
+#[derive(Debug)]
+pub enum PairOutcome {
+    Paired(PairedDevice, String),
+    WrongPin,
+    /// Too many wrong PINs lately; try again after this long.
+    Throttled(Duration),
+}
+
+impl Pairing {
+    /// Replace the PIN with a different one.
+    fn rotate(&mut self) {
+        let old = std::mem::take(&mut self.pin);
+        while self.pin.is_empty() || self.pin == old {
+            self.pin = generate_pin();
+        }
+        self.failed_attempts = 0;
+    }
+}
+
+/// Paired devices plus the current one-time pairing PIN.
+pub struct DeviceRegistry {
+    path: PathBuf,
+    devices: Mutex<Vec<PairedDevice>>,
+    pairing: Mutex<Pairing>,
+}
+
+pub type SharedRegistry = Arc<DeviceRegistry>;
+
+impl DeviceRegistry {
+    /// A registry with no paired devices that will be saved to `path`.
+    pub fn empty(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            devices: Mutex::new(Vec::new()),
+            pairing: Mutex::new(Pairing {
+                pin: generate_pin(),
+                failed_attempts: 0,
+                failures: 0,
+                locked_until: None,
+            }),
+        }
+    }
+
+    /// Load the paired devices at `path`, starting empty if it doesn't exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let registry = Self::empty(path);
+        match std::fs::read(&registry.path) {
+            Ok(data) => *registry.devices.lock().unwrap() = serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(registry)
+    }
+
+    pub fn current_pin(&self) -> String {
+        self.pairing.lock().unwrap().pin.clone()
+    }
+
+    /// Exchange the one-time PIN for a device token, at `now`.
+    ///
+    /// The PIN is replaced after every successful pairing and after too
+    /// many wrong guesses. Past a few wrong guesses in a row, pairing is
+    /// paused for a while that doubles with each further one, for everyone:
+    /// a LAN attacker can change addresses far faster than it can wait.
+    pub fn pair(&self, pin: &str, name: &str, now: Instant) -> std::io::Result<PairOutcome> {
+        {
+            let mut pairing = self.pairing.lock().unwrap();
+            if let Some(wait) = pairing.locked_until.and_then(|until| until.checked_duration_since(now)) {
+                if !wait.is_zero() {
+                    return Ok(PairOutcome::Throttled(wait));
+                }
+            }
+            if pin.trim() != pairing.pin {
+                pairing.failed_attempts += 1;
+                pairing.failures += 1;
+                if pairing.failed_attempts >= MAX_PIN_ATTEMPTS {
+                    pairing.rotate();
+                    println!("🔑 Too many wrong PINs, new pairing PIN: {}", pairing.pin);
+                }
+                if let Some(extra) = pairing.failures.checked_sub(FREE_PIN_ATTEMPTS + 1) {
+                    let backoff = PIN_BACKOFF.saturating_mul(1 << extra.min(16)).min(MAX_PIN_BACKOFF);
+                    pairing.locked_until = Some(now + backoff);
+                }
+                return Ok(PairOutcome::WrongPin);
+            }
+            pairing.rotate();
+            pairing.failures = 0;
+            pairing.locked_until = None;
+            println!("🔑 Device paired, next pairing PIN: {}", pairing.pin);
+        }
+
+        let token = generate_token();
+        let device = PairedDevice {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            token_hash: hash_token(&token),
+            paired_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+
+        // Only keep the device once it's on disk, so a restart agrees
+        let mut devices = self.devices.lock().unwrap();
+        let mut updated = devices.clone();
+        updated.push(device.clone());
+        save(&self.path, &updated)?;
+        *devices = updated;
+        Ok(PairOutcome::Paired(device, token))
+    }
+
+    /// The id of the device owning `token`, if it is still paired.
+    pub fn authenticate(&self, token: &str) -> Option<String> {
+        let token_hash = hash_token(token);
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| d.token_hash == token_hash)
+            .map(|d| d.id.clone())
+    }
+
+    pub fn is_paired(&self, device_id: &str) -> bool {
+        self.devices.lock().unwrap().iter().any(|d| d.id == device_id)
+    }
+
+    pub fn devices(&self) -> Vec<PairedDevice> {
+        self.devices.lock().unwrap().clone()
+    }
+
+    /// Returns whether a device was removed. If saving fails, it stays
+    /// paired.
+    pub fn revoke(&self, device_id: &str) -> std::io::Result<bool> {
+        let mut devices = self.devices.lock().unwrap();
+        let remaining: Vec<PairedDevice> = devices.iter().filter(|d| d.id != device_id).cloned().collect();
+        if remaining.len() == devices.len() {
+            return Ok(false);
+        }
+
+        save(&self.path, &remaining)?;
+        *devices = remaining;
+        Ok(true)
+    }
+}
+
+fn generate_pin() -> String {
+    format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000)
+}
+
+fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn save(path: &Path, devices: &[PairedDevice]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(devices)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+/// Print the pairing PIN and a QR code of the pairing link.
+pub fn print_pairing_info(registry: &DeviceRegistry, base_url: &str) {
+    let pin = registry.current_pin();
+    let url = format!("{}/pair.html?pin={}", base_url, pin);
+
+    println!("\n🔑 Pairing PIN: {}", pin);
+    println!("   Or scan to pair: {}", url);
+    if let Ok(code) = qrcode::QrCode::new(url.as_bytes()) {
+        let qr = code
+            .render::<qrcode::render::unicode::Dense1x2>()
+            .quiet_zone(true)
+            .build();
+        println!("{}", qr);
+    }
+}
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(Debug)]
+pub struct LocalOnly;
+
+impl warp::reject::Reject for LocalOnly {}
+
+/// Extract the id of the paired device making the request, from the token
+/// cookie or an `Authorization: Bearer` header.
+pub fn with_device(
+    registry: SharedRegistry,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::cookie::optional::<String>(TOKEN_COOKIE)
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |cookie: Option<String>, header: Option<String>| {
+            let registry = registry.clone();
+            async move {
+                let bearer = header.and_then(|h| h.strip_prefix("Bearer ").map(str::to_string));
+                bearer
+                    .or(cookie)
+                    .and_then(|token| registry.authenticate(&token))
+                    .ok_or_else(|| warp::reject::custom(Unauthorized))
+            }
+        })
+}
+
+/// Like `with_device`, for routes that don't care which device it is.
+pub fn require_device(
+    registry: SharedRegistry,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    with_device(registry).map(|_| ()).untuple_one()
+}
+
+/// Only let through requests from this machine.
+pub fn local_only() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(|addr: Option<SocketAddr>| async move {
+            match addr {
+                Some(addr) if addr.ip().is_loopback() => Ok(()),
+                _ => Err(warp::reject::custom(LocalOnly)),
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairRequest {
+    pub pin: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// `secure` marks the token cookie HTTPS-only, for when TLS is on.
+pub async fn handle_pair(
+    request: PairRequest,
+    registry: SharedRegistry,
+    secure: bool,
+) -> Result<warp::reply::Response, Rejection> {
+    let name = request
+        .name
+        .filter(|n| !n.trim().is_empty())
+        .unwrap_or_else(|| "Unnamed device".to_string());
+
+    match tokio::task::block_in_place(|| registry.pair(&request.pin, &name, Instant::now())) {
+        Ok(PairOutcome::Paired(device, token)) => {
+            println!("📱 Paired device: {} ({})", device.name, device.id);
+            let reply = warp::reply::json(&serde_json::json!({
+                "device_id": device.id,
+                "token": token
+            }));
+            // Ten years: the token lives until it is revoked
+            let cookie = format!(
+                "{}={}; Path=/; Max-Age=315360000; HttpOnly; SameSite=Strict{}",
+                TOKEN_COOKIE,
+                token,
+                if secure { "; Secure" } else { "" }
+            );
+            Ok(warp::reply::with_header(reply, "Set-Cookie", cookie).into_response())
+        }
+        Ok(PairOutcome::WrongPin) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Invalid PIN"})),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response()),
+        Ok(PairOutcome::Throttled(wait)) => {
+            // Whole seconds, rounded up so a retry right on time gets in
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            let reply = warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": "Too many wrong PINs, try again later",
+                    "retry_after": secs
+                })),
+                StatusCode::TOO_MANY_REQUESTS,
+            );
+            Ok(warp::reply::with_header(reply, "Retry-After", secs.to_string()).into_response())
+        }
+        Err(e) => {
+            eprintln!("❌ Error saving paired devices: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": "Failed to save device"})),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response())
+        }
+    }
+}
+
+pub async fn list_devices(registry: SharedRegistry) -> Result<impl Reply, Rejection> {
+    let devices: Vec<_> = registry
+        .devices()
+        .into_iter()
+        .map(|d| {
+            serde_json::json!({
+                "id": d.id,
+                "name": d.name,
+                "paired_at": d.paired_at
+            })
+        })
+        .collect();
+    Ok(warp::reply::json(&devices))
+}
+
+pub async fn revoke_device(id: String, registry: SharedRegistry) -> Result<warp::reply::Response, Rejection> {
+    match tokio::task::block_in_place(|| registry.revoke(&id)) {
+        Ok(true) => {
+            println!("🚫 Revoked device: {}", id);
+            Ok(warp::reply::json(&serde_json::json!({"revoked": id})).into_response())
+        }
+        Ok(false) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("❌ Error saving paired devices: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": "Failed to save devices"})),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response())
+        }
+    }
+}
+
+pub async fn pairing_info(registry: SharedRegistry) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&serde_json::json!({"pin": registry.current_pin()})))
+}
+
+/// Turn auth rejections into JSON errors; everything else keeps warp's default.
+pub async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Device not paired"})),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response())
+    } else if err.find::<LocalOnly>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "Only available from this computer"})),
+            StatusCode::FORBIDDEN,
+        )
+        .into_response())
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::temp_dir;
+
+    fn paired(outcome: std::io::Result<PairOutcome>) -> (PairedDevice, String) {
+        match outcome.unwrap() {
+            PairOutcome::Paired(device, token) => (device, token),
+            other => panic!("expected to pair, got {:?}", other),
+        }
+    }
+
+    fn registry() -> (DeviceRegistry, PathBuf) {
+        let path = temp_dir().join("devices.json");
+        (DeviceRegistry::empty(&path), path)
+    }
+
+    #[test]
+    fn pin_is_single_use() {
+        let (registry, path) = registry();
+        let pin = registry.current_pin();
+
+        let (device, token) = paired(registry.pair(&pin, "phone", Instant::now()));
+        assert_eq!(registry.authenticate(&token), Some(device.id));
+        assert!(matches!(registry.pair(&pin, "other", Instant::now()).unwrap(), PairOutcome::WrongPin));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn pin_rotates_after_failed_attempts() {
+        let (registry, _) = registry();
+        let pin = registry.current_pin();
+        let wrong = if pin == "000000" { "000001" } else { "000000" };
+
+        // Far enough apart that the backoff never gets in the way
+        let start = Instant::now();
+        for i in 0..MAX_PIN_ATTEMPTS {
+            let now = start + MAX_PIN_BACKOFF * i;
+            assert!(matches!(registry.pair(wrong, "guess", now).unwrap(), PairOutcome::WrongPin));
+        }
+        assert_ne!(registry.current_pin(), pin);
+    }
+
+    #[test]
+    fn wrong_pins_pause_pairing_for_longer_and_longer() {
+        let (registry, path) = registry();
+        // Picked afresh each time, as the PIN changes along the way
+        let wrong = || if registry.current_pin() == "000000" { "000001" } else { "000000" };
+        let start = Instant::now();
+
+        for _ in 0..FREE_PIN_ATTEMPTS {
+            assert!(matches!(registry.pair(wrong(), "guess", start).unwrap(), PairOutcome::WrongPin));
+        }
+        assert!(matches!(registry.pair(wrong(), "guess", start).unwrap(), PairOutcome::WrongPin));
+        // Even the right PIN isn't checked while paused
+        let pin = registry.current_pin();
+        assert!(matches!(
+            registry.pair(&pin, "phone", start).unwrap(),
+            PairOutcome::Throttled(wait) if wait == PIN_BACKOFF
+        ));
+
+        let later = start + PIN_BACKOFF;
+        assert!(matches!(registry.pair(wrong(), "guess", later).unwrap(), PairOutcome::WrongPin));
+        assert!(matches!(
+            registry.pair(wrong(), "guess", later).unwrap(),
+            PairOutcome::Throttled(wait) if wait == PIN_BACKOFF * 2
+        ));
+
+        // Capped, and a PIN change doesn't reset it
+        let mut now = later + PIN_BACKOFF * 2;
+        for _ in 0..20 {
+            registry.pair(wrong(), "guess", now).unwrap();
+            now += MAX_PIN_BACKOFF;
+        }
+        now -= MAX_PIN_BACKOFF;
+        assert!(matches!(
+            registry.pair(wrong(), "guess", now).unwrap(),
+            PairOutcome::Throttled(wait) if wait == MAX_PIN_BACKOFF
+        ));
+
+        // Pairing once the pause is over starts afresh
+        now += MAX_PIN_BACKOFF;
+        let pin = registry.current_pin();
+        paired(registry.pair(&pin, "phone", now));
+        assert!(matches!(registry.pair(wrong(), "guess", now).unwrap(), PairOutcome::WrongPin));
+        assert!(matches!(registry.pair(wrong(), "guess", now).unwrap(), PairOutcome::WrongPin));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn revoked_tokens_stop_working() {
+        let (registry, path) = registry();
+        let (device, token) = paired(registry.pair(&registry.current_pin(), "phone", Instant::now()));
+
+        let reloaded = DeviceRegistry::load(&path).unwrap();
+        assert!(reloaded.is_paired(&device.id));
+
+        assert!(registry.revoke(&device.id).unwrap());
+        assert_eq!(registry.authenticate(&token), None);
+        assert!(DeviceRegistry::load(&path).unwrap().devices().is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn failed_saves_change_nothing() {
+        let (registry, path) = registry();
+        let (device, token) = paired(registry.pair(&registry.current_pin(), "phone", Instant::now()));
+
+        // A directory where the temporary file goes makes every save fail
+        std::fs::create_dir_all(path.with_extension("json.tmp")).unwrap();
+        assert!(registry.revoke(&device.id).is_err());
+        assert_eq!(registry.authenticate(&token), Some(device.id));
+        assert!(registry.pair(&registry.current_pin(), "tablet", Instant::now()).is_err());
+        assert_eq!(registry.devices().len(), 1);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,122 @@
+use crate::events::TrackpadEvent;
+
+// Binary WebSocket frames for the events sent many times a second. The
+// first byte says which event it is; numbers are little-endian.
+
+/// `dx: f32, dy: f32`
+pub const MOVE: u8 = 0x01;
+/// `dx: f32, dy: f32`
+pub const SCROLL: u8 = 0x02;
+/// `button: u8`, an index into `BUTTONS`
+pub const CLICK: u8 = 0x03;
+pub const DRAG_START: u8 = 0x04;
+pub const DRAG_END: u8 = 0x05;
+
+/// Click buttons by their number in a `CLICK` frame.
+pub const BUTTONS: [&str; 3] = ["left", "right", "middle"];
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Empty,
+    UnknownOpcode(u8),
+    WrongLength { opcode: u8, expected: usize, actual: usize },
+    UnknownButton(u8),
+    /// NaN or infinity, which JSON can't carry either.
+    NotFinite,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "Empty frame"),
+            DecodeError::UnknownOpcode(opcode) => write!(f, "Unknown opcode 0x{:02x}", opcode),
+            DecodeError::WrongLength { opcode, expected, actual } => {
+                write!(f, "Opcode 0x{:02x} takes {} bytes, got {}", opcode, expected, actual)
+            }
+            DecodeError::UnknownButton(button) => write!(f, "Unknown button {}", button),
+            DecodeError::NotFinite => write!(f, "Deltas must be finite"),
+        }
+    }
+}
+
+/// Decode one binary frame into the event the equivalent JSON would give.
+pub fn decode(frame: &[u8]) -> Result<TrackpadEvent, DecodeError> {
+    let (&opcode, body) = frame.split_first().ok_or(DecodeError::Empty)?;
+    let expected = match opcode {
+        MOVE | SCROLL => 8,
+        CLICK => 1,
+        DRAG_START | DRAG_END => 0,
+        _ => return Err(DecodeError::UnknownOpcode(opcode)),
+    };
+    if body.len() != expected {
+        return Err(DecodeError::WrongLength { opcode, expected: expected + 1, actual: frame.len() });
+    }
+
+    match opcode {
+        MOVE | SCROLL => {
+            let dx = f32::from_le_bytes(body[0..4].try_into().unwrap());
+            let dy = f32::from_le_bytes(body[4..8].try_into().unwrap());
+            if !dx.is_finite() || !dy.is_finite() {
+                return Err(DecodeError::NotFinite);
+            }
+            let (dx, dy) = (dx as f64, dy as f64);
+            Ok(if opcode == MOVE {
+                TrackpadEvent::Move { dx, dy, t: None }
+            } else {
+                TrackpadEvent::Scroll { dx, dy }
+            })
+        }
+        CLICK => {
+            let button = BUTTONS.get(body[0] as usize).ok_or(DecodeError::UnknownButton(body[0]))?;
+            Ok(TrackpadEvent::Click { button: button.to_string() })
+        }
+        DRAG_START => Ok(TrackpadEvent::DragStart),
+        _ => Ok(TrackpadEvent::DragEnd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta_frame(opcode: u8, dx: f32, dy: f32) -> Vec<u8> {
+        let mut frame = vec![opcode];
+        frame.extend_from_slice(&dx.to_le_bytes());
+        frame.extend_from_slice(&dy.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn decodes_like_the_json() {
+        let json = r#"{"type":"move","dx":1.5,"dy":-0.25}"#;
+        let frame = delta_frame(MOVE, 1.5, -0.25);
+        assert_eq!(
+            serde_json::to_value(decode(&frame).unwrap()).unwrap(),
+            serde_json::to_value(serde_json::from_str::<TrackpadEvent>(json).unwrap()).unwrap()
+        );
+        // A quarter of the bytes for the event sent most
+        assert_eq!(frame.len(), 9);
+        assert!(json.len() > 3 * frame.len());
+
+        assert!(matches!(decode(&delta_frame(SCROLL, 0.0, 12.0)), Ok(TrackpadEvent::Scroll { dx, dy }) if dx == 0.0 && dy == 12.0));
+        assert!(matches!(decode(&[CLICK, 1]), Ok(TrackpadEvent::Click { button }) if button == "right"));
+        assert!(matches!(decode(&[DRAG_START]), Ok(TrackpadEvent::DragStart)));
+        assert!(matches!(decode(&[DRAG_END]), Ok(TrackpadEvent::DragEnd)));
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert_eq!(decode(&[]).unwrap_err(), DecodeError::Empty);
+        assert_eq!(decode(&[0x7f]).unwrap_err(), DecodeError::UnknownOpcode(0x7f));
+        assert_eq!(
+            decode(&[MOVE, 0, 0]).unwrap_err(),
+            DecodeError::WrongLength { opcode: MOVE, expected: 9, actual: 3 }
+        );
+        assert_eq!(
+            decode(&[DRAG_END, 0]).unwrap_err(),
+            DecodeError::WrongLength { opcode: DRAG_END, expected: 1, actual: 2 }
+        );
+        assert_eq!(decode(&[CLICK, 9]).unwrap_err(), DecodeError::UnknownButton(9));
+        assert_eq!(decode(&delta_frame(MOVE, f32::NAN, 0.0)).unwrap_err(), DecodeError::NotFinite);
+    }
+}
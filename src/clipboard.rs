@@ -0,0 +1,153 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::ClipboardItem;
+
+pub type ClipboardError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How often the host clipboard is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Access to the desktop clipboard.
+pub trait HostClipboard: Send {
+    fn get_text(&mut self) -> Result<String, ClipboardError>;
+    fn set_text(&mut self, text: &str) -> Result<(), ClipboardError>;
+}
+
+/// The real desktop clipboard (X11, or Wayland via wlr-data-control).
+pub struct ArboardClipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl ArboardClipboard {
+    pub fn new() -> Result<Self, ClipboardError> {
+        Ok(Self {
+            clipboard: arboard::Clipboard::new()?,
+        })
+    }
+}
+
+impl HostClipboard for ArboardClipboard {
+    fn get_text(&mut self) -> Result<String, ClipboardError> {
+        Ok(self.clipboard.get_text()?)
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+        Ok(self.clipboard.set_text(text)?)
+    }
+}
+
+/// Keeps the host clipboard and the connected clients in sync.
+///
+/// Text from clients is written to the host clipboard; host changes are picked
+/// up by polling and broadcast as `source: "Host"`. The last text seen in
+/// either direction is remembered so client text isn't echoed back as a host
+/// change.
+pub struct ClipboardBridge {
+    clipboard: Mutex<Box<dyn HostClipboard>>,
+    last_seen: Mutex<Option<String>>,
+}
+
+impl ClipboardBridge {
+    pub fn new(clipboard: Box<dyn HostClipboard>) -> Self {
+        let bridge = Self {
+            clipboard: Mutex::new(clipboard),
+            last_seen: Mutex::new(None),
+        };
+        // Don't broadcast whatever happened to be on the clipboard at startup
+        let current = bridge.clipboard.lock().unwrap().get_text().ok();
+        *bridge.last_seen.lock().unwrap() = current;
+        bridge
+    }
+
+    /// Put text received from a client on the host clipboard.
+    pub fn set_from_client(&self, text: &str) -> Result<(), ClipboardError> {
+        *self.last_seen.lock().unwrap() = Some(text.to_string());
+        self.clipboard.lock().unwrap().set_text(text)
+    }
+
+    /// Check the host clipboard, returning its text if it changed since the
+    /// last call (or since a client set it).
+    pub fn poll(&self) -> Option<String> {
+        let text = self.clipboard.lock().unwrap().get_text().ok()?;
+        if text.is_empty() {
+            return None;
+        }
+
+        let mut last_seen = self.last_seen.lock().unwrap();
+        if last_seen.as_deref() == Some(text.as_str()) {
+            return None;
+        }
+        *last_seen = Some(text.clone());
+        Some(text)
+    }
+}
+
+/// Watch the host clipboard on a dedicated thread (the clipboard APIs are
+/// blocking) and broadcast changes to all clients.
+pub fn spawn_host_watcher(
+    bridge: std::sync::Arc<ClipboardBridge>,
+    clipboard_tx: broadcast::Sender<ClipboardItem>,
+) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(POLL_INTERVAL);
+
+        if let Some(content) = bridge.poll() {
+            println!("📋 Host clipboard changed ({} chars)", content.chars().count());
+            let _ = clipboard_tx.send(ClipboardItem::new(content, "Host"));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Clipboard shared with the test so it can play the desktop user.
+    #[derive(Clone, Default)]
+    struct FakeClipboard {
+        text: Arc<Mutex<String>>,
+    }
+
+    impl HostClipboard for FakeClipboard {
+        fn get_text(&mut self) -> Result<String, ClipboardError> {
+            Ok(self.text.lock().unwrap().clone())
+        }
+
+        fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+            *self.text.lock().unwrap() = text.to_string();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn startup_contents_are_not_broadcast() {
+        let fake = FakeClipboard::default();
+        *fake.text.lock().unwrap() = "already there".into();
+        let bridge = ClipboardBridge::new(Box::new(fake));
+
+        assert_eq!(bridge.poll(), None);
+    }
+
+    #[test]
+    fn host_changes_are_reported_once() {
+        let fake = FakeClipboard::default();
+        let bridge = ClipboardBridge::new(Box::new(fake.clone()));
+
+        *fake.text.lock().unwrap() = "copied on desktop".into();
+        assert_eq!(bridge.poll().as_deref(), Some("copied on desktop"));
+        assert_eq!(bridge.poll(), None);
+    }
+
+    #[test]
+    fn client_text_lands_on_host_without_echo() {
+        let fake = FakeClipboard::default();
+        let bridge = ClipboardBridge::new(Box::new(fake.clone()));
+
+        bridge.set_from_client("from phone").unwrap();
+        assert_eq!(*fake.text.lock().unwrap(), "from phone");
+        assert_eq!(bridge.poll(), None);
+    }
+}
@@ -0,0 +1,472 @@
+use clap::{Args, Parser};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use crate::acceleration::{self, Curve};
+
+/// Config file picked up from the working directory when `--config` isn't given.
+pub const DEFAULT_CONFIG_FILE: &str = "./mobile-trackpad.toml";
+
+/// Control your computer's mouse, keyboard and clipboard from your phone.
+///
+/// Settings are read from the defaults, then the config file, then
+/// environment variables, then command-line flags; later sources win.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// TOML config file (default: ./mobile-trackpad.toml if it exists)
+    #[arg(long, env = "TRACKPAD_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Print the resolved configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    #[command(flatten)]
+    pub overrides: Overrides,
+}
+
+/// Settings that can be given as flags or environment variables.
+#[derive(Debug, Default, Args)]
+pub struct Overrides {
+    /// Port to listen on
+    #[arg(long, env = "TRACKPAD_PORT")]
+    pub port: Option<u16>,
+
+    /// Address to bind to
+    #[arg(long, env = "TRACKPAD_BIND")]
+    pub bind_address: Option<IpAddr>,
+
+    /// Serve web UI files from this directory before the embedded copies
+    #[arg(long, env = "TRACKPAD_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+
+    /// Directory uploaded files are stored in
+    #[arg(long, env = "TRACKPAD_UPLOADS_DIR")]
+    pub uploads_dir: Option<PathBuf>,
+
+    /// Directory for clipboard history, paired devices, the file index and certificates
+    #[arg(long, env = "TRACKPAD_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Largest accepted upload in bytes
+    #[arg(long, env = "TRACKPAD_MAX_UPLOAD_BYTES")]
+    pub max_upload_bytes: Option<u64>,
+
+    /// Seconds an uploaded file is kept
+    #[arg(long, env = "TRACKPAD_FILE_TTL")]
+    pub file_ttl_secs: Option<u64>,
+
+    /// Seconds an unfinished resumable upload is kept after its last chunk
+    #[arg(long, env = "TRACKPAD_UPLOAD_SESSION_TTL")]
+    pub upload_session_ttl_secs: Option<u64>,
+
+    /// Seconds between expired file cleanups
+    #[arg(long, env = "TRACKPAD_CLEANUP_INTERVAL")]
+    pub cleanup_interval_secs: Option<u64>,
+
+    /// Total bytes uploads may take up (default: unlimited)
+    #[arg(long, env = "TRACKPAD_MAX_TOTAL_BYTES")]
+    pub max_total_bytes: Option<u64>,
+
+    /// Most uploaded files kept at once (default: unlimited)
+    #[arg(long, env = "TRACKPAD_MAX_FILES")]
+    pub max_files: Option<usize>,
+
+    /// Free disk space uploads must leave on the uploads filesystem
+    #[arg(long, env = "TRACKPAD_MIN_FREE_BYTES")]
+    pub min_free_bytes: Option<u64>,
+
+    /// Delete the oldest files to make room instead of rejecting uploads
+    #[arg(
+        long,
+        env = "TRACKPAD_EVICT_OLDEST",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    pub evict_oldest: Option<bool>,
+
+    /// Also save finished uploads into this host directory, e.g. ~/Downloads
+    #[arg(long, env = "TRACKPAD_DELIVER_DIR")]
+    pub deliver_dir: Option<PathBuf>,
+
+    /// Whether delivered uploads are also kept for download (copy) or not (move)
+    #[arg(long, env = "TRACKPAD_DELIVER_MODE", value_enum)]
+    pub deliver_mode: Option<DeliverMode>,
+
+    /// Unpinned clipboard history entries to keep
+    #[arg(long, env = "TRACKPAD_CLIPBOARD_HISTORY")]
+    pub clipboard_history_size: Option<usize>,
+
+    /// Serve HTTPS/WSS
+    #[arg(
+        long,
+        env = "TRACKPAD_TLS",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    pub tls: Option<bool>,
+
+    /// PEM certificate for TLS (default: generated self-signed certificate)
+    #[arg(long, env = "TRACKPAD_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for TLS
+    #[arg(long, env = "TRACKPAD_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Pointer acceleration profile for clients that don't pick one
+    #[arg(long, env = "TRACKPAD_POINTER_PROFILE")]
+    pub pointer_profile: Option<String>,
+
+    /// Whether the content follows the fingers (natural) or the wheel (traditional)
+    #[arg(long, env = "TRACKPAD_SCROLL_DIRECTION", value_enum)]
+    pub scroll_direction: Option<ScrollDirection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: u16,
+    pub bind_address: IpAddr,
+    /// Development override for the embedded web UI.
+    pub static_dir: Option<PathBuf>,
+    pub uploads_dir: PathBuf,
+    pub data_dir: PathBuf,
+    pub max_upload_bytes: u64,
+    pub file_ttl_secs: u64,
+    pub upload_session_ttl_secs: u64,
+    pub cleanup_interval_secs: u64,
+    pub clipboard_history_size: usize,
+    pub quota: QuotaConfig,
+    pub deliver: DeliverConfig,
+    pub tls: TlsConfig,
+    pub pointer: PointerConfig,
+}
+
+/// Limits on what uploads may take up; the per-request cap is
+/// `max_upload_bytes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    pub max_total_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+    pub min_free_bytes: u64,
+    pub evict_oldest: bool,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            max_total_bytes: None,
+            max_files: None,
+            min_free_bytes: 268_435_456, // 256 MiB
+            evict_oldest: false,
+        }
+    }
+}
+
+/// Where finished uploads are saved on the host, besides the uploads store.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeliverConfig {
+    /// Off unless set; a leading `~` is the home directory.
+    pub dir: Option<PathBuf>,
+    pub mode: DeliverMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliverMode {
+    /// Keep the upload downloadable from the Files page too
+    #[default]
+    Copy,
+    /// Only keep the delivered file
+    Move,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+/// Pointer acceleration profiles, which clients pick by name in their
+/// `hello`. `flat`, `linear` and `adaptive` are always there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PointerConfig {
+    pub profile: String,
+    pub scroll_direction: ScrollDirection,
+    /// More profiles, or replacements for the built-in ones.
+    pub profiles: BTreeMap<String, Curve>,
+}
+
+impl Default for PointerConfig {
+    fn default() -> Self {
+        Self {
+            // The deltas as the client sent them
+            profile: "flat".to_string(),
+            scroll_direction: ScrollDirection::Natural,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollDirection {
+    /// The content follows the fingers, as on a phone
+    #[default]
+    Natural,
+    /// The content moves the way a mouse wheel would turn
+    Traditional,
+}
+
+impl PointerConfig {
+    /// Every profile by name, configured ones over the built-in ones.
+    pub fn all_profiles(&self) -> BTreeMap<String, Curve> {
+        let mut profiles = acceleration::builtin_profiles();
+        profiles.extend(self.profiles.clone());
+        profiles
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let profiles = self.all_profiles();
+        if !profiles.contains_key(&self.profile) {
+            return Err(format!("Unknown pointer profile: {}", self.profile));
+        }
+        for (name, curve) in &profiles {
+            curve.validate().map_err(|e| format!("Pointer profile {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 9999,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            static_dir: None,
+            uploads_dir: PathBuf::from("./uploads"),
+            data_dir: PathBuf::from("./data"),
+            max_upload_bytes: 1_073_741_824, // 1 GiB
+            file_ttl_secs: 3600,
+            upload_session_ttl_secs: 86400,
+            cleanup_interval_secs: 60,
+            clipboard_history_size: 200,
+            quota: QuotaConfig::default(),
+            deliver: DeliverConfig::default(),
+            tls: TlsConfig::default(),
+            pointer: PointerConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Resolve the configuration from all sources.
+    pub fn load(cli: &Cli) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply(&cli.overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Catch settings that parse but can't work.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cleanup_interval_secs == 0 {
+            return Err("cleanup_interval_secs must be at least 1".to_string());
+        }
+        self.pointer.validate()
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e).into())
+    }
+
+    /// Layer flags and environment variables over the file settings.
+    pub fn apply(&mut self, overrides: &Overrides) {
+        if let Some(port) = overrides.port {
+            self.port = port;
+        }
+        if let Some(bind_address) = overrides.bind_address {
+            self.bind_address = bind_address;
+        }
+        if let Some(static_dir) = &overrides.static_dir {
+            self.static_dir = Some(static_dir.clone());
+        }
+        if let Some(uploads_dir) = &overrides.uploads_dir {
+            self.uploads_dir = uploads_dir.clone();
+        }
+        if let Some(data_dir) = &overrides.data_dir {
+            self.data_dir = data_dir.clone();
+        }
+        if let Some(max_upload_bytes) = overrides.max_upload_bytes {
+            self.max_upload_bytes = max_upload_bytes;
+        }
+        if let Some(file_ttl_secs) = overrides.file_ttl_secs {
+            self.file_ttl_secs = file_ttl_secs;
+        }
+        if let Some(upload_session_ttl_secs) = overrides.upload_session_ttl_secs {
+            self.upload_session_ttl_secs = upload_session_ttl_secs;
+        }
+        if let Some(cleanup_interval_secs) = overrides.cleanup_interval_secs {
+            self.cleanup_interval_secs = cleanup_interval_secs;
+        }
+        if let Some(clipboard_history_size) = overrides.clipboard_history_size {
+            self.clipboard_history_size = clipboard_history_size;
+        }
+        if let Some(max_total_bytes) = overrides.max_total_bytes {
+            self.quota.max_total_bytes = Some(max_total_bytes);
+        }
+        if let Some(max_files) = overrides.max_files {
+            self.quota.max_files = Some(max_files);
+        }
+        if let Some(min_free_bytes) = overrides.min_free_bytes {
+            self.quota.min_free_bytes = min_free_bytes;
+        }
+        if let Some(evict_oldest) = overrides.evict_oldest {
+            self.quota.evict_oldest = evict_oldest;
+        }
+        if let Some(deliver_dir) = &overrides.deliver_dir {
+            self.deliver.dir = Some(deliver_dir.clone());
+        }
+        if let Some(deliver_mode) = overrides.deliver_mode {
+            self.deliver.mode = deliver_mode;
+        }
+        if let Some(tls) = overrides.tls {
+            self.tls.enabled = tls;
+        }
+        if let Some(cert) = &overrides.tls_cert {
+            self.tls.cert = Some(cert.clone());
+        }
+        if let Some(key) = &overrides.tls_key {
+            self.tls.key = Some(key.clone());
+        }
+        if let Some(profile) = &overrides.pointer_profile {
+            self.pointer.profile = profile.clone();
+        }
+        if let Some(scroll_direction) = overrides.scroll_direction {
+            self.pointer.scroll_direction = scroll_direction;
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always representable as TOML")
+    }
+
+    pub fn files_index_path(&self) -> PathBuf {
+        self.data_dir.join("files.json")
+    }
+
+    pub fn upload_sessions_path(&self) -> PathBuf {
+        self.data_dir.join("upload_sessions.json")
+    }
+
+    /// Unfinished resumable uploads, kept apart so they're never listed.
+    pub fn partial_uploads_dir(&self) -> PathBuf {
+        self.uploads_dir.join(".partial")
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.data_dir.join("clipboard_history.json")
+    }
+
+    pub fn devices_path(&self) -> PathBuf {
+        self.data_dir.join("devices.json")
+    }
+
+    pub fn tls_dir(&self) -> PathBuf {
+        self.data_dir.join("tls")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_settings_fill_in_defaults() {
+        let config: Config = toml::from_str("port = 8080\n[tls]\nenabled = true\n").unwrap();
+        assert_eq!(config.port, 8080);
+        assert!(config.tls.enabled);
+        assert_eq!(config.file_ttl_secs, Config::default().file_ttl_secs);
+    }
+
+    #[test]
+    fn overrides_win_over_file() {
+        let mut config: Config = toml::from_str("port = 8080\nfile_ttl_secs = 60\n").unwrap();
+        config.apply(&Overrides {
+            port: Some(7000),
+            tls: Some(true),
+            ..Overrides::default()
+        });
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.file_ttl_secs, 60);
+        assert!(config.tls.enabled);
+    }
+
+    #[test]
+    fn deliver_table_parses() {
+        let config: Config = toml::from_str("[deliver]\ndir = \"~/Downloads\"\nmode = \"move\"\n").unwrap();
+        assert_eq!(config.deliver.dir, Some(PathBuf::from("~/Downloads")));
+        assert_eq!(config.deliver.mode, DeliverMode::Move);
+        assert_eq!(Config::default().deliver.mode, DeliverMode::Copy);
+    }
+
+    #[test]
+    fn pointer_profiles_add_to_the_built_in_ones() {
+        let config: Config = toml::from_str(
+            "[pointer]\nprofile = \"mine\"\nscroll_direction = \"traditional\"\n[pointer.profiles.mine]\ncurve = \"piecewise\"\npoints = [[0, 1], [2, 2]]\n",
+        )
+        .unwrap();
+        assert_eq!(config.pointer.validate(), Ok(()));
+        assert_eq!(config.pointer.scroll_direction, ScrollDirection::Traditional);
+        let names: Vec<String> = config.pointer.all_profiles().into_keys().collect();
+        assert_eq!(names, ["adaptive", "flat", "linear", "mine"]);
+
+        let mut config = Config::default();
+        assert_eq!(config.pointer.validate(), Ok(()));
+        assert_eq!(config.pointer.scroll_direction, ScrollDirection::Natural);
+        config.apply(&Overrides { pointer_profile: Some("turbo".into()), ..Overrides::default() });
+        assert!(config.pointer.validate().is_err());
+    }
+
+    #[test]
+    fn zero_cleanup_interval_is_rejected() {
+        let mut config: Config = toml::from_str("cleanup_interval_secs = 0\n").unwrap();
+        assert!(config.validate().is_err());
+        config.apply(&Overrides {
+            cleanup_interval_secs: Some(1),
+            ..Overrides::default()
+        });
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("prot = 8080\n").is_err());
+    }
+
+    #[test]
+    fn printed_config_round_trips() {
+        let config = Config::default();
+        let reparsed: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(reparsed.to_toml(), config.to_toml());
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config::{DeliverConfig, DeliverMode};
+use crate::files::{self, FileInfo, FileStore};
+
+/// How many `name (n).ext` variants are tried before giving up.
+const MAX_RENAME_ATTEMPTS: usize = 1000;
+
+/// Add a finished upload, whose contents are already at its path in
+/// `file_storage`, to the store and deliver it to the host folder if one
+/// is configured. Returns where it was delivered.
+///
+/// Moved files leave the store. A failed delivery only costs the copy on
+/// the host: the file is kept in the store as if delivery were off.
+pub fn store_upload(info: FileInfo, file_storage: &FileStore, config: &DeliverConfig) -> std::io::Result<Option<PathBuf>> {
+    let Some(dir) = &config.dir else {
+        file_storage.insert(info)?;
+        return Ok(None);
+    };
+
+    match deliver(&file_storage.path(&info.id), &info.filename, &expand_home(dir), config.mode) {
+        Ok(path) => {
+            println!("📂 Delivered {} to {}", info.filename, path.display());
+            if config.mode == DeliverMode::Copy {
+                file_storage.insert(info)?;
+            }
+            Ok(Some(path))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to deliver {} to {}: {}", info.filename, dir.display(), e);
+            file_storage.insert(info)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Copy or move `source` into `dir` as `filename`, adding ` (1)`, ` (2)`, ...
+/// before the extension rather than overwriting anything already there.
+pub fn deliver(source: &Path, filename: &str, dir: &Path, mode: DeliverMode) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let filename = files::sanitize_filename(filename);
+
+    for attempt in 0..MAX_RENAME_ATTEMPTS {
+        let dest = dir.join(numbered(&filename, attempt));
+        // Both only ever create `dest`, so a file that appears meanwhile is safe
+        let placed = match mode {
+            DeliverMode::Copy => copy_new(source, &dest),
+            DeliverMode::Move => std::fs::hard_link(source, &dest).or_else(|e| match e.kind() {
+                ErrorKind::AlreadyExists => Err(e),
+                // Another filesystem, or one without hard links
+                _ => copy_new(source, &dest),
+            }),
+        };
+        match placed {
+            Ok(()) => {
+                if mode == DeliverMode::Move {
+                    if let Err(e) = std::fs::remove_file(source) {
+                        eprintln!("❌ Error removing {}: {}", source.display(), e);
+                    }
+                }
+                return Ok(dest);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(ErrorKind::AlreadyExists, "too many files with that name"))
+}
+
+/// `filename` with ` (n)` before its extension; `n == 0` leaves it alone.
+pub fn numbered(filename: &str, n: usize) -> String {
+    if n == 0 {
+        return filename.to_string();
+    }
+    match filename.rfind('.') {
+        Some(dot) if dot > 0 => format!("{} ({}){}", &filename[..dot], n, &filename[dot..]),
+        _ => format!("{} ({})", filename, n),
+    }
+}
+
+/// Copy to `dest`, failing if it exists and leaving nothing behind on error.
+fn copy_new(source: &Path, dest: &Path) -> std::io::Result<()> {
+    let mut out = std::fs::OpenOptions::new().write(true).create_new(true).open(dest)?;
+    let copied = std::fs::File::open(source).and_then(|mut input| std::io::copy(&mut input, &mut out));
+    if let Err(e) = copied {
+        drop(out);
+        std::fs::remove_file(dest).ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Config files can say `~/Downloads`; there's no shell to expand it.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::{file_info, temp_dir};
+
+    #[test]
+    fn numbers_before_the_extension() {
+        assert_eq!(numbered("photo.jpg", 0), "photo.jpg");
+        assert_eq!(numbered("photo.jpg", 2), "photo (2).jpg");
+        assert_eq!(numbered("archive.tar.gz", 1), "archive.tar (1).gz");
+        assert_eq!(numbered("README", 1), "README (1)");
+        assert_eq!(numbered(".bashrc", 1), ".bashrc (1)");
+    }
+
+    #[test]
+    fn never_overwrites_existing_files() {
+        let dir = temp_dir();
+        let dest = dir.join("Downloads");
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(dest.join("photo.jpg"), b"older").unwrap();
+        let source = dir.join("blob");
+        std::fs::write(&source, b"newer").unwrap();
+
+        let copied = deliver(&source, "photo.jpg", &dest, DeliverMode::Copy).unwrap();
+        assert_eq!(copied, dest.join("photo (1).jpg"));
+        assert!(source.exists());
+
+        let moved = deliver(&source, "../photo.jpg", &dest, DeliverMode::Move).unwrap();
+        assert_eq!(moved, dest.join("photo (2).jpg"));
+        assert!(!source.exists());
+
+        assert_eq!(std::fs::read(dest.join("photo.jpg")).unwrap(), b"older");
+        assert_eq!(std::fs::read(moved).unwrap(), b"newer");
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn moved_uploads_leave_the_store() {
+        let dir = temp_dir();
+        let store = FileStore::empty(dir.join("files.json"), dir.join("uploads"));
+        let info = file_info("notes.txt", 5, 100, 200);
+        std::fs::create_dir_all(store.uploads_dir()).unwrap();
+        std::fs::write(store.path(&info.id), b"notes").unwrap();
+        let config = DeliverConfig {
+            dir: Some(dir.join("Downloads")),
+            mode: DeliverMode::Move,
+        };
+
+        let delivered = store_upload(info.clone(), &store, &config).unwrap();
+        assert_eq!(delivered, Some(dir.join("Downloads/notes.txt")));
+        assert_eq!(store.get(&info.id), None);
+        assert!(!store.path(&info.id).exists());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
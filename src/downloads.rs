@@ -0,0 +1,334 @@
+use base64::Engine;
+use serde::Deserialize;
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+use warp::Rejection;
+
+use crate::files::{FileInfo, FileStorage};
+
+/// Bytes read at a time while streaming a file out.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes looked at to work out the content type.
+pub const SNIFF_LEN: usize = 512;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DownloadQuery {
+    /// Show the file in the browser instead of saving it.
+    #[serde(default)]
+    pub inline: bool,
+}
+
+/// A satisfiable byte range, both ends inclusive.
+#[derive(Debug, PartialEq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    /// No usable `Range` header: send the whole file.
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header against a file of `size` bytes.
+///
+/// Only single ranges are honoured; anything else gets the whole file,
+/// which RFC 9110 allows.
+fn parse_range(range: Option<&str>, size: u64) -> RangeRequest {
+    let Some(spec) = range.and_then(|r| r.trim().strip_prefix("bytes=")) else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        // bytes=-500: the last 500 bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(suffix) => (size.saturating_sub(suffix), size.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, size.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+            _ => return RangeRequest::Full,
+        },
+    };
+
+    if size == 0 || start >= size {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(ByteRange { start, end })
+    }
+}
+
+/// Content type from the first bytes of a file.
+///
+/// Markup that browsers would run scripts in (HTML, SVG, XML) never comes
+/// out of this, only `text/plain`, so viewing a file inline can't run
+/// anything on this origin.
+pub fn sniff_content_type(head: &[u8]) -> &'static str {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (b"\xff\xfb", "audio/mpeg"),
+        (b"\xff\xf3", "audio/mpeg"),
+        (b"PK\x03\x04", "application/zip"),
+    ];
+
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return mime;
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {}
+        }
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return match &head[8..12] {
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        };
+    }
+    if looks_like_text(head) {
+        return "text/plain; charset=utf-8";
+    }
+    "application/octet-stream"
+}
+
+/// UTF-8 without NULs, allowing for a character cut off at the end.
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() - e.valid_up_to() < 4,
+    }
+}
+
+/// RFC 6266 `Content-Disposition` with the name twice: an ASCII-only
+/// `filename` for old clients and the exact UTF-8 one as RFC 8187
+/// (formerly 5987) `filename*`.
+pub fn content_disposition(disposition: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let mut encoded = String::with_capacity(filename.len());
+    for &byte in filename.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("{}; filename=\"{}\"; filename*=UTF-8''{}", disposition, fallback, encoded)
+}
+
+/// The content digest where there is one; files never change, so the id
+/// does too otherwise.
+fn etag(info: &FileInfo) -> String {
+    format!("\"{}\"", info.sha256.as_deref().unwrap_or(&info.id))
+}
+
+/// RFC 3230 `Digest` value: the base64 SHA-256 of the whole file.
+fn digest_header(sha256: &str) -> Option<String> {
+    let bytes = (0..sha256.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(sha256.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(format!("sha-256={}", base64::engine::general_purpose::STANDARD.encode(bytes)))
+}
+
+fn http_date(secs: u64) -> String {
+    httpdate::fmt_http_date(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+}
+
+/// `If-Range` holds when it names the current version, by ETag or date.
+fn if_range_matches(if_range: &str, info: &FileInfo) -> bool {
+    let if_range = if_range.trim();
+    if_range == etag(info) || if_range == http_date(info.uploaded_at)
+}
+
+pub async fn handle_download(
+    file_id: String,
+    query: DownloadQuery,
+    range: Option<String>,
+    if_range: Option<String>,
+    storage: FileStorage,
+) -> Result<Response<Body>, Rejection> {
+    let Some(info) = storage.get(&file_id) else {
+        eprintln!("❌ File ID not found in storage: {}", file_id);
+        return Err(warp::reject::not_found());
+    };
+
+    let file_path = storage.contents_path(&info);
+    let mut file = match tokio::fs::File::open(&file_path).await {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("❌ File not found: {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    let size = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            eprintln!("❌ Error reading file: {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+
+    let (content_type, disposition) = if query.inline {
+        let mut head = vec![0; SNIFF_LEN.min(size as usize)];
+        if let Err(e) = file.read_exact(&mut head).await {
+            eprintln!("❌ Error reading file: {}", e);
+            return Err(warp::reject::not_found());
+        }
+        (sniff_content_type(&head), "inline")
+    } else {
+        ("application/octet-stream", "attachment")
+    };
+
+    // A stale If-Range means the client's partial copy is useless
+    let range = match &if_range {
+        Some(if_range) if !if_range_matches(if_range, &info) => None,
+        _ => range,
+    };
+
+    let mut builder = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag(&info))
+        .header(header::LAST_MODIFIED, http_date(info.uploaded_at))
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, content_disposition(disposition, &info.filename))
+        // Uploaded content never gets to act as a page of this origin
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox");
+    if let Some(digest) = info.sha256.as_deref().and_then(digest_header) {
+        builder = builder.header("Digest", digest);
+    }
+
+    let (builder, start, len) = match parse_range(range.as_deref(), size) {
+        RangeRequest::Full => {
+            println!("📥 Download: {} ({} bytes)", info.filename, size);
+            (builder.status(StatusCode::OK), 0, size)
+        }
+        RangeRequest::Partial(ByteRange { start, end }) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size)),
+            start,
+            end - start + 1,
+        ),
+        RangeRequest::Unsatisfiable => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+
+    if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+        eprintln!("❌ Error reading file: {}", e);
+        return Err(warp::reject::not_found());
+    }
+    let stream = ReaderStream::with_capacity(file.take(len), STREAM_CHUNK_SIZE);
+    Ok(builder
+        .header(header::CONTENT_LENGTH, len)
+        .body(Body::wrap_stream(stream))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(start: u64, end: u64) -> RangeRequest {
+        RangeRequest::Partial(ByteRange { start, end })
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range(None, 1000), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=0-99"), 1000), partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=900-"), 1000), partial(900, 999));
+        assert_eq!(parse_range(Some("bytes=-100"), 1000), partial(900, 999));
+        assert_eq!(parse_range(Some("bytes=-5000"), 1000), partial(0, 999));
+        assert_eq!(parse_range(Some("bytes=500-5000"), 1000), partial(500, 999));
+    }
+
+    #[test]
+    fn falls_back_or_refuses_odd_ranges() {
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 1000), RangeRequest::Full);
+        assert_eq!(parse_range(Some("items=0-1"), 1000), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=9-2"), 1000), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=1000-"), 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn digest_header_is_base64_of_the_raw_hash() {
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(
+            digest_header(abc).as_deref(),
+            Some("sha-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=")
+        );
+    }
+
+    #[test]
+    fn content_disposition_encodes_any_filename() {
+        assert_eq!(
+            content_disposition("attachment", "report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+        );
+        assert_eq!(
+            content_disposition("inline", "na\u{ef}ve r\u{e9}sum\u{e9}.txt"),
+            "inline; filename=\"na_ve r_sum_.txt\"; filename*=UTF-8''na%C3%AFve%20r%C3%A9sum%C3%A9.txt"
+        );
+        // Nothing can end the quoted string or the header early
+        assert_eq!(
+            content_disposition("attachment", "a\";\r\nx=\\.txt"),
+            "attachment; filename=\"a_;__x=_.txt\"; filename*=UTF-8''a%22%3B%0D%0Ax%3D%5C.txt"
+        );
+    }
+
+    #[test]
+    fn sniffs_media_and_never_markup() {
+        assert_eq!(sniff_content_type(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff_content_type(b"\0\0\0\x18ftypmp42\0\0\0\0"), "video/mp4");
+        assert_eq!(sniff_content_type(b"\0\0\0\x18ftypheic\0\0\0\0"), "image/heic");
+        assert_eq!(sniff_content_type(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff_content_type(b"<html><script>alert(1)</script>"), "text/plain; charset=utf-8");
+        assert_eq!(sniff_content_type(&"caf\u{e9}".as_bytes()[..4]), "text/plain; charset=utf-8");
+        assert_eq!(sniff_content_type(b"\x00\x01\x02\x03"), "application/octet-stream");
+    }
+}
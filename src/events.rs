@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::keys::KeyAction;
+
+/// Which virtual device an event group is meant for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    Pointer,
+    Keyboard,
+}
+
+/// Everything a client can ask the computer to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TrackpadEvent {
+    #[serde(rename = "move")]
+    Move {
+        dx: f64,
+        dy: f64,
+        /// When the client saw it, in milliseconds on its own clock.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        t: Option<f64>,
+    },
+    #[serde(rename = "click")]
+    Click { button: String },
+    #[serde(rename = "scroll")]
+    Scroll { dx: f64, dy: f64 },
+    #[serde(rename = "drag_start")]
+    DragStart,
+    #[serde(rename = "drag_end")]
+    DragEnd,
+    #[serde(rename = "swipe")]
+    Swipe { direction: String },
+    #[serde(rename = "arrow_key")]
+    ArrowKey { key: String },
+    #[serde(rename = "key")]
+    Key {
+        key: String,
+        #[serde(default)]
+        action: KeyAction,
+        #[serde(default)]
+        modifiers: Vec<String>,
+    },
+    #[serde(rename = "type_text")]
+    TypeText {
+        text: String,
+        #[serde(default)]
+        delay_ms: Option<u64>,
+    },
+    #[serde(rename = "clipboard")]
+    Clipboard { content: String },
+    /// Several moves and scrolls at once, applied as one step.
+    #[serde(rename = "batch")]
+    Batch { events: Vec<MotionDelta> },
+}
+
+/// One step of pointer motion in a `batch`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MotionDelta {
+    #[serde(default)]
+    pub kind: MotionKind,
+    pub dx: f64,
+    pub dy: f64,
+    /// When the client saw it, in milliseconds on its own clock.
+    #[serde(default)]
+    pub t: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionKind {
+    #[default]
+    Move,
+    Scroll,
+}
+
+impl TrackpadEvent {
+    /// The `type` this event is sent with.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            TrackpadEvent::Move { .. } => "move",
+            TrackpadEvent::Click { .. } => "click",
+            TrackpadEvent::Scroll { .. } => "scroll",
+            TrackpadEvent::DragStart => "drag_start",
+            TrackpadEvent::DragEnd => "drag_end",
+            TrackpadEvent::Swipe { .. } => "swipe",
+            TrackpadEvent::ArrowKey { .. } => "arrow_key",
+            TrackpadEvent::Key { .. } => "key",
+            TrackpadEvent::TypeText { .. } => "type_text",
+            TrackpadEvent::Clipboard { .. } => "clipboard",
+            TrackpadEvent::Batch { .. } => "batch",
+        }
+    }
+}
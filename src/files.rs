@@ -0,0 +1,671 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::config::Config;
+use crate::quota::Usage;
+use crate::thumbnails;
+
+/// Longest filename kept, in bytes; what most filesystems allow.
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Extensions up to this many bytes survive shortening a long filename.
+const MAX_EXTENSION_BYTES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub id: String,
+    pub filename: String,
+    pub size: u64,
+    pub uploaded_at: u64,
+    pub expires_at: u64,
+    /// Hex SHA-256 of the contents; missing for files stored before
+    /// digests were recorded.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Pinned files never expire and are never evicted.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Where a file shared from this computer lives; it is served from
+    /// there rather than copied into the uploads directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_path: Option<PathBuf>,
+    /// Sniffed from the contents in the background after the file is
+    /// stored; missing until then.
+    #[serde(default)]
+    pub mime: Option<String>,
+    /// Size in pixels of images that could be decoded.
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Whether there is a preview at `/files/{id}/thumbnail`.
+    #[serde(default)]
+    pub thumbnail: bool,
+}
+
+/// A change to the file list, sent to every connected client.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum FileEvent {
+    #[serde(rename = "file_added")]
+    Added { file: FileInfo },
+    #[serde(rename = "file_updated")]
+    Updated { file: FileInfo },
+    #[serde(rename = "file_removed")]
+    Removed { id: String },
+    /// A file offered from this computer, with the link to fetch it.
+    #[serde(rename = "file_shared")]
+    Shared { file: FileInfo, url: String },
+}
+
+/// Uploaded files, stored in `uploads_dir` under their id, with their
+/// metadata persisted as a JSON index so they survive restarts.
+pub struct FileStore {
+    index_path: PathBuf,
+    uploads_dir: PathBuf,
+    files: Mutex<HashMap<String, FileInfo>>,
+    events: broadcast::Sender<FileEvent>,
+}
+
+pub type FileStorage = Arc<FileStore>;
+
+impl FileStore {
+    /// An empty store that will be saved to `index_path` on the first change.
+    pub fn empty(index_path: impl Into<PathBuf>, uploads_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            index_path: index_path.into(),
+            uploads_dir: uploads_dir.into(),
+            files: Mutex::new(HashMap::new()),
+            events: broadcast::channel(100).0,
+        }
+    }
+
+    /// Load the index at `index_path`, starting empty if it doesn't exist yet.
+    pub fn load(index_path: impl Into<PathBuf>, uploads_dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let store = Self::empty(index_path, uploads_dir);
+        match std::fs::read(&store.index_path) {
+            Ok(data) => {
+                let files: Vec<FileInfo> = serde_json::from_slice(&data)?;
+                *store.files.lock().unwrap() = files.into_iter().map(|f| (f.id.clone(), f)).collect();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(store)
+    }
+
+    /// Where the contents of file `id` live.
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.uploads_dir.join(id)
+    }
+
+    /// Where the contents of `info` are read from.
+    pub fn contents_path(&self, info: &FileInfo) -> PathBuf {
+        info.host_path.clone().unwrap_or_else(|| self.path(&info.id))
+    }
+
+    /// Where the thumbnail of file `id` is kept.
+    pub fn thumbnail_path(&self, id: &str) -> PathBuf {
+        self.uploads_dir.join(".thumbnails").join(format!("{}.jpg", id))
+    }
+
+    pub fn uploads_dir(&self) -> &Path {
+        &self.uploads_dir
+    }
+
+    /// Every change to the file list from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<FileEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: FileEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    pub fn get(&self, id: &str) -> Option<FileInfo> {
+        self.files.lock().unwrap().get(id).cloned()
+    }
+
+    /// All files, newest first.
+    pub fn list(&self) -> Vec<FileInfo> {
+        let mut files: Vec<FileInfo> = self.files.lock().unwrap().values().cloned().collect();
+        files.sort_by_key(|f| std::cmp::Reverse(f.uploaded_at));
+        files
+    }
+
+    /// Count and total size of the files in the uploads directory.
+    pub fn usage(&self) -> Usage {
+        self.usage_of(|_| true)
+    }
+
+    /// Count and total size of the uploads eviction can't touch.
+    pub fn pinned_usage(&self) -> Usage {
+        self.usage_of(|f| f.pinned)
+    }
+
+    /// Files shared from the host take up no space of ours, so never count.
+    fn usage_of(&self, filter: impl Fn(&FileInfo) -> bool) -> Usage {
+        let files = self.files.lock().unwrap();
+        let matching = files.values().filter(|f| f.host_path.is_none() && filter(f));
+        Usage {
+            files: matching.clone().count(),
+            bytes: matching.map(|f| f.size).sum(),
+            pending: 0,
+        }
+    }
+
+    /// The oldest upload that may be evicted.
+    pub fn oldest_unpinned(&self) -> Option<FileInfo> {
+        self.files
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|f| !f.pinned && f.host_path.is_none())
+            .min_by_key(|f| f.uploaded_at)
+            .cloned()
+    }
+
+    pub fn insert(&self, info: FileInfo) -> std::io::Result<()> {
+        self.add(info, |file| FileEvent::Added { file })
+    }
+
+    /// Add a file offered from this computer, announced with its link.
+    pub fn share(&self, info: FileInfo) -> std::io::Result<()> {
+        self.add(info, |file| FileEvent::Shared {
+            url: format!("/download/{}", file.id),
+            file,
+        })
+    }
+
+    fn add(&self, info: FileInfo, event: impl FnOnce(FileInfo) -> FileEvent) -> std::io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        files.insert(info.id.clone(), info.clone());
+        save(&self.index_path, &files)?;
+        self.notify(event(info));
+        Ok(())
+    }
+
+    /// Change file `id` in place; returns the updated file, or `None` if
+    /// there is no such id.
+    pub fn update(&self, id: &str, change: impl FnOnce(&mut FileInfo)) -> std::io::Result<Option<FileInfo>> {
+        let mut files = self.files.lock().unwrap();
+        let Some(info) = files.get_mut(id) else {
+            return Ok(None);
+        };
+        change(info);
+        let info = info.clone();
+
+        save(&self.index_path, &files)?;
+        self.notify(FileEvent::Updated { file: info.clone() });
+        Ok(Some(info))
+    }
+
+    /// Delete file `id`, contents included, and return what it was. Files
+    /// shared from the host are only forgotten, never deleted.
+    pub fn remove(&self, id: &str) -> std::io::Result<Option<FileInfo>> {
+        let mut files = self.files.lock().unwrap();
+        let Some(info) = files.remove(id) else {
+            return Ok(None);
+        };
+        remove_blob(&self.path(id));
+        remove_blob(&self.thumbnail_path(id));
+        save(&self.index_path, &files)?;
+        self.notify(FileEvent::Removed { id: id.to_string() });
+        Ok(Some(info))
+    }
+
+    /// Drop unpinned files that expired before `now`, contents included,
+    /// and return them.
+    pub fn remove_expired(&self, now: u64) -> std::io::Result<Vec<FileInfo>> {
+        let mut files = self.files.lock().unwrap();
+        let expired: Vec<FileInfo> = files
+            .values()
+            .filter(|f| !f.pinned && now > f.expires_at)
+            .cloned()
+            .collect();
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+
+        for info in &expired {
+            files.remove(&info.id);
+            remove_blob(&self.path(&info.id));
+            remove_blob(&self.thumbnail_path(&info.id));
+        }
+        save(&self.index_path, &files)?;
+        for info in &expired {
+            self.notify(FileEvent::Removed { id: info.id.clone() });
+        }
+        Ok(expired)
+    }
+
+    /// Bring the index and the uploads directory back in line after a
+    /// restart: forget entries whose contents are gone or incomplete, drop
+    /// expired files, and delete uploads the index doesn't know about.
+    ///
+    /// Only files named like upload ids are deleted, so anything else that
+    /// happens to live in the directory is left alone.
+    pub fn reconcile(&self, now: u64) -> std::io::Result<()> {
+        {
+            let mut files = self.files.lock().unwrap();
+            files.retain(|id, info| {
+                let intact = std::fs::metadata(self.contents_path(info)).is_ok_and(|m| m.len() == info.size);
+                if !intact {
+                    println!("🧹 Forgetting {} ({}): contents missing or changed", info.filename, id);
+                    remove_blob(&self.thumbnail_path(id));
+                }
+                intact
+            });
+            save(&self.index_path, &files)?;
+        }
+
+        for info in self.remove_expired(now)? {
+            println!("🧹 Removed expired file {} ({})", info.filename, info.id);
+        }
+
+        let entries = match std::fs::read_dir(&self.uploads_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let files = self.files.lock().unwrap();
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if Uuid::parse_str(&name).is_ok() && !files.contains_key(&name) && entry.file_type()?.is_file() {
+                println!("🧹 Removing orphaned upload {}", name);
+                remove_blob(&entry.path());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A client-supplied filename made safe to store, show and send back:
+/// only the last path component, without control or bidi override
+/// characters, trimmed and at most `MAX_FILENAME_BYTES` long. Names with
+/// nothing left become `"unnamed"`.
+pub fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|&c| !c.is_control() && !matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'))
+        .collect();
+    let name = name.trim();
+    if name.chars().all(|c| c == '.') {
+        return "unnamed".to_string();
+    }
+    if name.len() <= MAX_FILENAME_BYTES {
+        return name.to_string();
+    }
+
+    // Shorten the stem so the extension, which says what the file is, stays
+    let extension = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_BYTES => &name[dot..],
+        _ => "",
+    };
+    let mut stem_len = MAX_FILENAME_BYTES - extension.len();
+    while !name.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    format!("{}{}", name[..stem_len].trim_end(), extension)
+}
+
+/// A client-supplied hex SHA-256 in canonical (lowercase) form, or `None`
+/// if it isn't one.
+pub fn normalize_sha256(digest: &str) -> Option<String> {
+    let digest = digest.trim();
+    (digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())).then(|| digest.to_ascii_lowercase())
+}
+
+/// Hex SHA-256 of the file at `path`.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn remove_blob(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("❌ Error removing {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Write via a temp file so a crash never leaves a truncated index.
+fn save(path: &Path, files: &HashMap<String, FileInfo>) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut files: Vec<&FileInfo> = files.values().collect();
+    files.sort_by_key(|f| f.uploaded_at);
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(&files)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+pub async fn list_files(file_storage: FileStorage) -> Result<impl warp::Reply, warp::Rejection> {
+    // Add cache control header to prevent excessive requests
+    Ok(warp::reply::with_header(
+        warp::reply::json(&file_storage.list()),
+        "Cache-Control",
+        "no-cache, must-revalidate",
+    ))
+}
+
+pub async fn delete_file(id: String, file_storage: FileStorage) -> Result<impl warp::Reply, warp::Rejection> {
+    // Rewrites the index file
+    match tokio::task::block_in_place(|| file_storage.remove(&id)) {
+        Ok(Some(info)) => {
+            println!("🗑️  Deleted file: {}", info.filename);
+            Ok(warp::reply::json(&serde_json::json!({"deleted": id})).into_response())
+        }
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("❌ Error saving file index: {}", e);
+            Ok(error_reply("Failed to save file index", StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+/// Changes to a file; anything left out stays as it is.
+#[derive(Debug, Deserialize)]
+pub struct FileUpdate {
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub pinned: Option<bool>,
+    /// Keep the file this many seconds longer, counting from now if it is
+    /// already overdue.
+    #[serde(default)]
+    pub extend_secs: Option<u64>,
+}
+
+pub async fn update_file(
+    id: String,
+    update: FileUpdate,
+    file_storage: FileStorage,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let filename = match update.filename.as_deref().map(str::trim) {
+        Some("") => return Ok(error_reply("Filename must not be empty", StatusCode::BAD_REQUEST)),
+        filename => filename.map(sanitize_filename),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let updated = tokio::task::block_in_place(|| {
+        file_storage.update(&id, |info| {
+            if let Some(filename) = filename {
+                info.filename = filename;
+            }
+            if let Some(pinned) = update.pinned {
+                info.pinned = pinned;
+            }
+            if let Some(extend_secs) = update.extend_secs {
+                info.expires_at = info.expires_at.max(now).saturating_add(extend_secs);
+            }
+        })
+    });
+    match updated {
+        Ok(Some(info)) => Ok(warp::reply::json(&info).into_response()),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("❌ Error saving file index: {}", e);
+            Ok(error_reply("Failed to save file index", StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+/// A file on this computer to offer to the phones.
+#[derive(Debug, Deserialize)]
+pub struct ShareRequest {
+    pub path: PathBuf,
+    /// Shown instead of the file's own name.
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+pub async fn share_host_file(
+    request: ShareRequest,
+    file_storage: FileStorage,
+    config: Arc<Config>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // The server's working directory means nothing to whoever is asking
+    if !request.path.is_absolute() {
+        return Ok(error_reply("Path must be absolute", StatusCode::BAD_REQUEST));
+    }
+    let path = match std::fs::canonicalize(&request.path) {
+        Ok(path) if path.is_file() => path,
+        Ok(_) => return Ok(error_reply("Not a regular file", StatusCode::BAD_REQUEST)),
+        Err(e) => {
+            eprintln!("❌ Can't share {}: {}", request.path.display(), e);
+            return Ok(error_reply("File not found", StatusCode::NOT_FOUND));
+        }
+    };
+    let filename = sanitize_filename(
+        request
+            .filename
+            .as_deref()
+            .or_else(|| path.file_name().and_then(|name| name.to_str()))
+            .unwrap_or_default(),
+    );
+
+    // Hashing reads the whole file
+    let shared = tokio::task::block_in_place(|| {
+        let size = std::fs::metadata(&path)?.len();
+        let sha256 = sha256_file(&path)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let info = FileInfo {
+            id: Uuid::new_v4().to_string(),
+            filename,
+            size,
+            uploaded_at: now,
+            expires_at: now + config.file_ttl_secs,
+            sha256: Some(sha256),
+            pinned: request.pinned,
+            host_path: Some(path.clone()),
+            mime: None,
+            width: None,
+            height: None,
+            thumbnail: false,
+        };
+        file_storage.share(info.clone())?;
+        Ok::<_, std::io::Error>(info)
+    });
+    match shared {
+        Ok(info) => {
+            println!("📤 Shared {} as {}", path.display(), info.filename);
+            thumbnails::spawn(file_storage, info.id.clone());
+            Ok(warp::reply::json(&serde_json::json!({
+                "url": format!("/download/{}", info.id),
+                "file": info
+            }))
+            .into_response())
+        }
+        Err(e) => {
+            eprintln!("❌ Error sharing {}: {}", path.display(), e);
+            Ok(error_reply("Failed to share file", StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+pub(crate) fn error_reply(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": message})), status).into_response()
+}
+
+/// Fixtures shared by the tests of modules that keep files on disk.
+#[cfg(test)]
+pub mod test_support {
+    use super::FileInfo;
+    use std::path::PathBuf;
+
+    /// A fresh, not yet created directory under the system temp directory.
+    pub fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("mobile-trackpad-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    /// An unpinned upload with a new id and nothing sniffed about it yet.
+    pub fn file_info(filename: &str, size: u64, uploaded_at: u64, expires_at: u64) -> FileInfo {
+        FileInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            filename: filename.to_string(),
+            size,
+            uploaded_at,
+            expires_at,
+            sha256: None,
+            pinned: false,
+            host_path: None,
+            mime: None,
+            width: None,
+            height: None,
+            thumbnail: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_support::{file_info, temp_dir};
+
+    fn add_file(store: &FileStore, contents: &[u8], expires_at: u64) -> FileInfo {
+        let info = file_info("photo.jpg", contents.len() as u64, 100, expires_at);
+        std::fs::create_dir_all(store.uploads_dir()).unwrap();
+        std::fs::write(store.path(&info.id), contents).unwrap();
+        store.insert(info.clone()).unwrap();
+        info
+    }
+
+    #[test]
+    fn sanitizes_filenames() {
+        assert_eq!(sanitize_filename("photo.jpg"), "photo.jpg");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\report.pdf"), "report.pdf");
+        assert_eq!(sanitize_filename("a\"b\r\nSet-Cookie: x.txt"), "a\"bSet-Cookie: x.txt");
+        assert_eq!(sanitize_filename("evil\u{202e}gpj.exe"), "evilgpj.exe");
+        assert_eq!(sanitize_filename("  caf\u{e9}.txt "), "caf\u{e9}.txt");
+        assert_eq!(sanitize_filename(".."), "unnamed");
+        assert_eq!(sanitize_filename("dir/"), "unnamed");
+        assert_eq!(sanitize_filename(""), "unnamed");
+    }
+
+    #[test]
+    fn shortens_long_filenames_keeping_the_extension() {
+        let long = format!("{}.jpeg", "\u{e9}".repeat(200));
+        let short = sanitize_filename(&long);
+        assert!(short.len() <= MAX_FILENAME_BYTES);
+        assert!(short.ends_with("\u{e9}.jpeg"));
+
+        let no_extension = sanitize_filename(&"x".repeat(300));
+        assert_eq!(no_extension.len(), MAX_FILENAME_BYTES);
+    }
+
+    #[test]
+    fn survives_reload() {
+        let dir = temp_dir();
+        let store = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        let info = add_file(&store, b"hello", 1000);
+
+        let reloaded = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        assert_eq!(reloaded.get(&info.id), Some(info));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn pinned_files_outlive_their_expiry() {
+        let dir = temp_dir();
+        let store = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        let pinned = add_file(&store, b"keep me", 10);
+        let expired = add_file(&store, b"drop me", 10);
+        store.update(&pinned.id, |f| f.pinned = true).unwrap();
+        let mut events = store.subscribe();
+
+        let removed = store.remove_expired(500).unwrap();
+        assert_eq!(removed, std::slice::from_ref(&expired));
+        assert!(store.get(&pinned.id).is_some());
+        assert_eq!(store.oldest_unpinned(), None);
+        assert!(matches!(events.try_recv(), Ok(FileEvent::Removed { id }) if id == expired.id));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn shared_host_files_are_never_deleted() {
+        let dir = temp_dir();
+        let store = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        let host_file = dir.join("report.pdf");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&host_file, b"%PDF-").unwrap();
+        let shared = FileInfo {
+            host_path: Some(host_file.clone()),
+            ..file_info("report.pdf", 5, 1, 10)
+        };
+        store.share(shared.clone()).unwrap();
+
+        assert_eq!(store.contents_path(&shared), host_file);
+        assert_eq!(store.usage().files, 0);
+        assert_eq!(store.oldest_unpinned(), None);
+
+        let reloaded = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        reloaded.reconcile(5).unwrap();
+        assert_eq!(reloaded.get(&shared.id), Some(shared.clone()));
+        reloaded.remove_expired(500).unwrap();
+        assert_eq!(reloaded.get(&shared.id), None);
+        assert!(host_file.exists());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn reconcile_cleans_up_both_sides() {
+        let dir = temp_dir();
+        let store = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        let kept = add_file(&store, b"kept", 1000);
+        let expired = add_file(&store, b"expired", 10);
+        let missing = add_file(&store, b"missing", 1000);
+        let truncated = add_file(&store, b"truncated", 1000);
+        std::fs::remove_file(store.path(&missing.id)).unwrap();
+        std::fs::write(store.path(&truncated.id), b"trunc").unwrap();
+
+        // Left behind by an upload that never finished
+        let orphan = store.path(&Uuid::new_v4().to_string());
+        std::fs::write(&orphan, b"partial").unwrap();
+        let unrelated = store.uploads_dir().join("notes.txt");
+        std::fs::write(&unrelated, b"not ours").unwrap();
+
+        let reloaded = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        reloaded.reconcile(500).unwrap();
+
+        assert_eq!(reloaded.list(), std::slice::from_ref(&kept));
+        assert!(reloaded.path(&kept.id).exists());
+        assert!(!reloaded.path(&expired.id).exists());
+        assert!(!reloaded.path(&truncated.id).exists());
+        assert!(!orphan.exists());
+        assert!(unrelated.exists());
+
+        // The cleaned-up index is what gets loaded next time
+        let again = FileStore::load(dir.join("files.json"), dir.join("uploads")).unwrap();
+        assert_eq!(again.list(), [kept]);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
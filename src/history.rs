@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::files::error_reply;
+use crate::ClipboardItem;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub content: String,
+    pub timestamp: u64,
+    pub source: String,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Bounded clipboard history persisted as a JSON array, oldest first.
+///
+/// Pinned entries never count towards the bound and are never evicted.
+pub struct ClipboardHistory {
+    path: PathBuf,
+    max_entries: usize,
+    entries: Mutex<Vec<HistoryEntry>>,
+}
+
+pub type SharedHistory = Arc<ClipboardHistory>;
+
+impl ClipboardHistory {
+    /// An empty history that will be saved to `path` on the first change.
+    pub fn empty(path: impl Into<PathBuf>, max_entries: usize) -> Self {
+        Self {
+            path: path.into(),
+            max_entries,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Load the history at `path`, starting empty if it doesn't exist yet.
+    pub fn load(path: impl Into<PathBuf>, max_entries: usize) -> std::io::Result<Self> {
+        let history = Self::empty(path, max_entries);
+        match std::fs::read(&history.path) {
+            Ok(data) => *history.entries.lock().unwrap() = serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(history)
+    }
+
+    /// Entries oldest first, the order they are replayed in.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<HistoryEntry> {
+        self.entries.lock().unwrap().iter().find(|e| e.id == id).cloned()
+    }
+
+    pub fn add(&self, item: &ClipboardItem) -> std::io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(HistoryEntry {
+            id: item.id.clone(),
+            content: item.content.clone(),
+            timestamp: item.timestamp,
+            source: item.source.clone(),
+            pinned: false,
+        });
+
+        // Evict the oldest unpinned entries beyond the bound
+        let mut unpinned = entries.iter().filter(|e| !e.pinned).count();
+        entries.retain(|e| {
+            if e.pinned || unpinned <= self.max_entries {
+                true
+            } else {
+                unpinned -= 1;
+                false
+            }
+        });
+
+        save(&self.path, &entries)
+    }
+
+    /// Returns the updated entry, or `None` if there is no such id.
+    pub fn set_pinned(&self, id: &str, pinned: bool) -> std::io::Result<Option<HistoryEntry>> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter_mut().find(|e| e.id == id) else {
+            return Ok(None);
+        };
+        entry.pinned = pinned;
+        let entry = entry.clone();
+
+        save(&self.path, &entries)?;
+        Ok(Some(entry))
+    }
+
+    /// Returns whether an entry was removed.
+    pub fn remove(&self, id: &str) -> std::io::Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| e.id != id);
+        if entries.len() == before {
+            return Ok(false);
+        }
+
+        save(&self.path, &entries)?;
+        Ok(true)
+    }
+}
+
+/// Write via a temp file so a crash never leaves a truncated history.
+fn save(path: &Path, entries: &[HistoryEntry]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(entries)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+/// Record every clipboard broadcast from clients and the host.
+pub async fn record_history(history: SharedHistory, clipboard_tx: broadcast::Sender<ClipboardItem>) {
+    let mut clipboard_rx = clipboard_tx.subscribe();
+    loop {
+        match clipboard_rx.recv().await {
+            // System messages are notifications, not clipboard contents
+            Ok(item) if item.source == "System" => {}
+            Ok(item) => {
+                if let Err(e) = tokio::task::block_in_place(|| history.add(&item)) {
+                    eprintln!("❌ Error saving clipboard history: {}", e);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                eprintln!("⚠️  Clipboard history skipped {} items", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+pub async fn list_history(history: SharedHistory) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut entries = history.entries();
+    entries.reverse(); // newest first, like /files
+
+    Ok(warp::reply::with_header(
+        warp::reply::json(&entries),
+        "Cache-Control",
+        "no-cache, must-revalidate",
+    ))
+}
+
+pub async fn get_history_entry(
+    id: String,
+    history: SharedHistory,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match history.get(&id) {
+        Some(entry) => Ok(warp::reply::json(&entry)),
+        None => Err(warp::reject::not_found()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinRequest {
+    pub pinned: bool,
+}
+
+pub async fn pin_history_entry(
+    id: String,
+    request: PinRequest,
+    history: SharedHistory,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match tokio::task::block_in_place(|| history.set_pinned(&id, request.pinned)) {
+        Ok(Some(entry)) => Ok(warp::reply::json(&entry).into_response()),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("❌ Error saving clipboard history: {}", e);
+            Ok(error_reply("Failed to save history", StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+pub async fn delete_history_entry(
+    id: String,
+    history: SharedHistory,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match tokio::task::block_in_place(|| history.remove(&id)) {
+        Ok(true) => Ok(warp::reply::json(&serde_json::json!({"deleted": id})).into_response()),
+        Ok(false) => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("❌ Error saving clipboard history: {}", e);
+            Ok(error_reply("Failed to save history", StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::temp_dir;
+
+    fn temp_path(name: &str) -> PathBuf {
+        temp_dir().join(name)
+    }
+
+    fn item(content: &str) -> ClipboardItem {
+        ClipboardItem::new(content.to_string(), "Client")
+    }
+
+    #[test]
+    fn evicts_oldest_unpinned_entries() {
+        let path = temp_path("history.json");
+        let history = ClipboardHistory::load(&path, 2).unwrap();
+        let first = item("first");
+        history.add(&first).unwrap();
+        history.set_pinned(&first.id, true).unwrap();
+        history.add(&item("second")).unwrap();
+        history.add(&item("third")).unwrap();
+        history.add(&item("fourth")).unwrap();
+
+        let contents: Vec<_> = history.entries().into_iter().map(|e| e.content).collect();
+        assert_eq!(contents, ["first", "third", "fourth"]);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn survives_reload() {
+        let path = temp_path("history.json");
+        let history = ClipboardHistory::load(&path, 10).unwrap();
+        let kept = item("kept");
+        let deleted = item("deleted");
+        history.add(&kept).unwrap();
+        history.add(&deleted).unwrap();
+        history.set_pinned(&kept.id, true).unwrap();
+        assert!(history.remove(&deleted.id).unwrap());
+        assert!(!history.remove(&deleted.id).unwrap());
+
+        let reloaded = ClipboardHistory::load(&path, 10).unwrap();
+        assert_eq!(reloaded.entries(), history.entries());
+        assert!(reloaded.get(&kept.id).unwrap().pinned);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,913 @@
+use evdev::{uinput::{VirtualDevice, VirtualDeviceBuilder}, AttributeSet, EventType, InputEvent, RelativeAxisType, Key};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::config::ScrollDirection;
+use crate::keys::{self, KeyAction};
+use crate::events::{Device, MotionDelta, MotionKind, TrackpadEvent};
+
+/// Sink for the evdev events `MouseController` produces.
+///
+/// Each `emit` call is one group of events, normally terminated by a
+/// SYN_REPORT, exactly as it would be written to a uinput device.
+pub trait InputBackend: Send {
+    fn emit(&mut self, device: Device, events: &[InputEvent]) -> io::Result<()>;
+
+    /// The devices events can be sent to.
+    fn devices(&self) -> Vec<Device> {
+        vec![Device::Pointer, Device::Keyboard]
+    }
+}
+
+/// Real input through /dev/uinput: a virtual mouse plus a virtual keyboard.
+pub struct UinputBackend {
+    pointer: VirtualDevice,
+    keyboard: VirtualDevice,
+}
+
+impl UinputBackend {
+    pub fn new() -> io::Result<Self> {
+        let mut keys = AttributeSet::<Key>::new();
+        keys.insert(Key::BTN_LEFT);
+        keys.insert(Key::BTN_RIGHT);
+        keys.insert(Key::BTN_MIDDLE);
+        keys.insert(Key::KEY_LEFT);
+        keys.insert(Key::KEY_RIGHT);
+        keys.insert(Key::KEY_UP);
+        keys.insert(Key::KEY_DOWN);
+        keys.insert(Key::KEY_LEFTALT);
+        
+        let mut relative_axes = AttributeSet::<RelativeAxisType>::new();
+        relative_axes.insert(RelativeAxisType::REL_X);
+        relative_axes.insert(RelativeAxisType::REL_Y);
+        relative_axes.insert(RelativeAxisType::REL_WHEEL);
+        relative_axes.insert(RelativeAxisType::REL_HWHEEL);
+        relative_axes.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+        relative_axes.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+        
+        let pointer = VirtualDeviceBuilder::new()?
+            .name("Mobile Trackpad Virtual Mouse")
+            .with_keys(&keys)?
+            .with_relative_axes(&relative_axes)?
+            .build()?;
+        
+        // Separate keyboard device so compositors don't treat the mouse as a keyboard
+        let keyboard = VirtualDeviceBuilder::new()?
+            .name("Mobile Trackpad Virtual Keyboard")
+            .with_keys(&keys::keyboard_keys())?
+            .build()?;
+        
+        Ok(Self { pointer, keyboard })
+    }
+}
+
+impl InputBackend for UinputBackend {
+    fn emit(&mut self, device: Device, events: &[InputEvent]) -> io::Result<()> {
+        match device {
+            Device::Pointer => self.pointer.emit(events),
+            Device::Keyboard => self.keyboard.emit(events),
+        }
+    }
+}
+
+/// Used when uinput can't be opened: every event fails with the original
+/// reason so clients get an error reply instead of the server refusing to start.
+pub struct DisabledBackend {
+    reason: String,
+}
+
+impl DisabledBackend {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self { reason: reason.into() }
+    }
+}
+
+impl InputBackend for DisabledBackend {
+    fn emit(&mut self, _device: Device, _events: &[InputEvent]) -> io::Result<()> {
+        Err(io::Error::other(format!("Input devices unavailable: {}", self.reason)))
+    }
+
+    fn devices(&self) -> Vec<Device> {
+        Vec::new()
+    }
+}
+
+/// One recorded `emit` call as `(type, code, value)` triples.
+#[cfg(test)]
+pub type RecordedGroup = (Device, Vec<(EventType, u16, i32)>);
+
+/// In-memory backend that records every emitted group, for tests.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingBackend {
+    groups: std::sync::Arc<Mutex<Vec<RecordedGroup>>>,
+}
+
+#[cfg(test)]
+impl RecordingBackend {
+    pub fn groups(&self) -> Vec<RecordedGroup> {
+        self.groups.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl InputBackend for RecordingBackend {
+    fn emit(&mut self, device: Device, events: &[InputEvent]) -> io::Result<()> {
+        let events = events
+            .iter()
+            .map(|e| (e.event_type(), e.code(), e.value()))
+            .collect();
+        self.groups.lock().unwrap().push((device, events));
+        Ok(())
+    }
+}
+
+/// Most deltas in one `batch`.
+pub const MAX_BATCH_LEN: usize = 256;
+
+/// Scroll deltas per wheel click.
+const SCROLL_STEP: f64 = 10.0;
+
+/// High-resolution wheel units per click, as the kernel counts them.
+const HI_RES_PER_NOTCH: i32 = 120;
+
+/// `(dx, dy)` as the client sent it.
+type Delta = (f64, f64);
+
+/// The fractions of a pixel and of a wheel click not sent yet. Kept per
+/// connection, so slow movements add up instead of being rounded away.
+#[derive(Debug, Default)]
+pub struct Remainder {
+    x: f64,
+    y: f64,
+    wheel: Wheel,
+    hwheel: Wheel,
+}
+
+/// Scrolling on one wheel axis not sent yet.
+#[derive(Debug, Default)]
+struct Wheel {
+    /// Fraction of a high-resolution unit.
+    hi_res: f64,
+    /// High-resolution units sent since the last whole click.
+    notch: i32,
+}
+
+impl Wheel {
+    /// High-resolution units and whole clicks for scrolling `notches`. The
+    /// clicks follow the units sent, so both always add up to the same.
+    fn take(&mut self, notches: f64) -> (i32, i32) {
+        let hi_res = take_whole(&mut self.hi_res, notches * HI_RES_PER_NOTCH as f64);
+        // A huge scroll already saturated `hi_res`; keep the sum from wrapping
+        self.notch = self.notch.saturating_add(hi_res);
+        let clicks = self.notch / HI_RES_PER_NOTCH;
+        self.notch -= clicks * HI_RES_PER_NOTCH;
+        (hi_res, clicks)
+    }
+}
+
+/// Whole units of `delta` plus what was left in `remainder`, which keeps the
+/// new fraction. Rounds towards zero so the fraction has the sign of the motion.
+/// A total too big for an `f64` is dropped, as it would leave a NaN behind.
+fn take_whole(remainder: &mut f64, delta: f64) -> i32 {
+    let total = *remainder + delta;
+    if !total.is_finite() {
+        return 0;
+    }
+    let whole = total.trunc();
+    *remainder = total - whole;
+    whole as i32
+}
+
+impl Remainder {
+    /// The relative events for moving by `motion` and turning the wheels
+    /// by `notches` (horizontal, vertical), without the SYN_REPORT; empty
+    /// if it all stays below a unit.
+    fn events(&mut self, motion: Delta, notches: Delta) -> Vec<InputEvent> {
+        let x = take_whole(&mut self.x, motion.0);
+        let y = take_whole(&mut self.y, motion.1);
+        let (hwheel_hi_res, hwheel) = self.hwheel.take(notches.0);
+        let (wheel_hi_res, wheel) = self.wheel.take(notches.1);
+
+        [
+            (RelativeAxisType::REL_X, x),
+            (RelativeAxisType::REL_Y, y),
+            (RelativeAxisType::REL_WHEEL_HI_RES, wheel_hi_res),
+            (RelativeAxisType::REL_WHEEL, wheel),
+            (RelativeAxisType::REL_HWHEEL_HI_RES, hwheel_hi_res),
+            (RelativeAxisType::REL_HWHEEL, hwheel),
+        ]
+        .into_iter()
+        .filter(|(_, value)| *value != 0)
+        .map(|(axis, value)| InputEvent::new(EventType::RELATIVE, axis.0, value))
+        .collect()
+    }
+}
+
+/// Emit `events` as one group, unless there are none.
+fn emit_motion(backend: &mut dyn InputBackend, mut events: Vec<InputEvent>) -> io::Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    events.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+    backend.emit(Device::Pointer, &events)
+}
+
+/// Total move and scroll of a batch, checking it first.
+fn sum_batch(deltas: &[MotionDelta]) -> Result<(Delta, Delta), String> {
+    if deltas.len() > MAX_BATCH_LEN {
+        return Err(format!("Batch too long (max {} events)", MAX_BATCH_LEN));
+    }
+    let timestamps: Vec<f64> = deltas.iter().filter_map(|d| d.t).collect();
+    if timestamps.windows(2).any(|pair| pair[1] < pair[0]) {
+        return Err("Batch timestamps go backwards".to_string());
+    }
+
+    let mut motion = (0.0, 0.0);
+    let mut scroll = (0.0, 0.0);
+    for delta in deltas {
+        let sum = match delta.kind {
+            MotionKind::Move => &mut motion,
+            MotionKind::Scroll => &mut scroll,
+        };
+        sum.0 += delta.dx;
+        sum.1 += delta.dy;
+    }
+    if ![motion.0, motion.1, scroll.0, scroll.1].iter().all(|sum| sum.is_finite()) {
+        return Err("Batch adds up to too much motion".to_string());
+    }
+    Ok((motion, scroll))
+}
+
+pub struct MouseController {
+    backend: Mutex<Box<dyn InputBackend>>,
+    scroll_direction: ScrollDirection,
+}
+
+impl MouseController {
+    pub fn new(backend: Box<dyn InputBackend>, scroll_direction: ScrollDirection) -> Self {
+        Self {
+            backend: Mutex::new(backend),
+            scroll_direction,
+        }
+    }
+
+    /// Wheel clicks, horizontal and vertical, for scrolling by `(dx, dy)`.
+    fn notches(&self, scroll: Delta) -> Delta {
+        // Natural: the content follows the fingers. A positive wheel scrolls
+        // up and a positive horizontal wheel right, so both get inverted.
+        let sign = match self.scroll_direction {
+            ScrollDirection::Natural => 1.0,
+            ScrollDirection::Traditional => -1.0,
+        };
+        (-sign * scroll.0 / SCROLL_STEP, sign * scroll.1 / SCROLL_STEP)
+    }
+
+    pub fn devices(&self) -> Vec<Device> {
+        self.backend.lock().unwrap().devices()
+    }
+
+    /// Act on `event` from a client whose leftover motion is in `remainder`.
+    pub fn handle_event(&self, event: TrackpadEvent, remainder: &mut Remainder) -> Result<(), Box<dyn std::error::Error>> {
+        let mut backend = self.backend.lock().unwrap();
+        
+        match event {
+            TrackpadEvent::Move { dx, dy, .. } => {
+                emit_motion(&mut **backend, remainder.events((dx, dy), (0.0, 0.0)))?;
+            }
+            TrackpadEvent::Click { button } => {
+                let key = match button.as_str() {
+                    "left" => Key::BTN_LEFT,
+                    "right" => Key::BTN_RIGHT,
+                    "middle" => Key::BTN_MIDDLE,
+                    _ => return Err(format!("Unknown button: {}", button).into()),
+                };
+                
+                let events_down = vec![
+                    InputEvent::new(EventType::KEY, key.0, 1),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ];
+                backend.emit(Device::Pointer, &events_down)?;
+                
+                let events_up = vec![
+                    InputEvent::new(EventType::KEY, key.0, 0),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ];
+                backend.emit(Device::Pointer, &events_up)?;
+            }
+            TrackpadEvent::Scroll { dx, dy } => {
+                emit_motion(&mut **backend, remainder.events((0.0, 0.0), self.notches((dx, dy))))?;
+            }
+            TrackpadEvent::Batch { events } => {
+                // One SYN_REPORT for the lot, so it lands as a single motion
+                let (motion, scroll) = sum_batch(&events)?;
+                emit_motion(&mut **backend, remainder.events(motion, self.notches(scroll)))?;
+            }
+            TrackpadEvent::DragStart => {
+                let events = vec![
+                    InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 1),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ];
+                backend.emit(Device::Pointer, &events)?;
+            }
+            TrackpadEvent::DragEnd => {
+                let events = vec![
+                    InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 0),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ];
+                backend.emit(Device::Pointer, &events)?;
+            }
+            TrackpadEvent::Swipe { direction } => {
+                let arrow_key = match direction.as_str() {
+                    "left" => Key::KEY_LEFT,
+                    "right" => Key::KEY_RIGHT,
+                    _ => return Err(format!("Unknown swipe direction: {}", direction).into()),
+                };
+                
+                backend.emit(Device::Pointer, &[
+                    InputEvent::new(EventType::KEY, Key::KEY_LEFTALT.0, 1),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ])?;
+                
+                backend.emit(Device::Pointer, &[
+                    InputEvent::new(EventType::KEY, arrow_key.0, 1),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ])?;
+                
+                backend.emit(Device::Pointer, &[
+                    InputEvent::new(EventType::KEY, arrow_key.0, 0),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ])?;
+                
+                backend.emit(Device::Pointer, &[
+                    InputEvent::new(EventType::KEY, Key::KEY_LEFTALT.0, 0),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ])?;
+            }
+            TrackpadEvent::ArrowKey { key } => {
+                let arrow_key = match key.as_str() {
+                    "up" => Key::KEY_UP,
+                    "down" => Key::KEY_DOWN,
+                    "left" => Key::KEY_LEFT,
+                    "right" => Key::KEY_RIGHT,
+                    _ => return Err(format!("Unknown arrow key: {}", key).into()),
+                };
+                
+                backend.emit(Device::Pointer, &[
+                    InputEvent::new(EventType::KEY, arrow_key.0, 1),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ])?;
+                
+                backend.emit(Device::Pointer, &[
+                    InputEvent::new(EventType::KEY, arrow_key.0, 0),
+                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                ])?;
+            }
+            TrackpadEvent::Key { key, action, modifiers } => {
+                // Resolve every name before emitting so a typo never leaves a modifier held
+                let key = keys::parse_key(&key)?;
+                let modifiers = modifiers
+                    .iter()
+                    .map(|name| keys::parse_key(name))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if action != KeyAction::Release {
+                    for modifier in &modifiers {
+                        backend.emit(Device::Keyboard, &[
+                            InputEvent::new(EventType::KEY, modifier.0, 1),
+                            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                        ])?;
+                    }
+                    
+                    backend.emit(Device::Keyboard, &[
+                        InputEvent::new(EventType::KEY, key.0, 1),
+                        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                    ])?;
+                }
+                
+                if action != KeyAction::Press {
+                    backend.emit(Device::Keyboard, &[
+                        InputEvent::new(EventType::KEY, key.0, 0),
+                        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                    ])?;
+                    
+                    for modifier in modifiers.iter().rev() {
+                        backend.emit(Device::Keyboard, &[
+                            InputEvent::new(EventType::KEY, modifier.0, 0),
+                            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+                        ])?;
+                    }
+                }
+            }
+            TrackpadEvent::TypeText { text, delay_ms } => {
+                drop(backend);
+                self.type_text(&text, delay_ms, &AtomicBool::new(false))?;
+            }
+            TrackpadEvent::Clipboard { .. } => {
+                // Clipboard is handled separately in websocket handler
+                // This is a no-op for the mouse controller
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Type `text` a character at a time, sleeping between keys, so call it
+    /// from a blocking thread. Stops early once `cancel` is set.
+    pub fn type_text(&self, text: &str, delay_ms: Option<u64>, cancel: &AtomicBool) -> Result<(), Box<dyn std::error::Error>> {
+        let chars = text.chars().count();
+        if chars > keys::MAX_TYPE_TEXT_CHARS {
+            return Err(format!("Text too long (max {} characters)", keys::MAX_TYPE_TEXT_CHARS).into());
+        }
+        let delay_ms = delay_ms.unwrap_or(keys::DEFAULT_TYPE_DELAY_MS).min(keys::MAX_TYPE_DELAY_MS);
+        if chars as u64 * delay_ms > keys::MAX_TYPE_DURATION_MS {
+            return Err(format!(
+                "Text would take too long to type (max {} seconds)",
+                keys::MAX_TYPE_DURATION_MS / 1000
+            )
+            .into());
+        }
+        let delay = std::time::Duration::from_millis(delay_ms);
+        
+        for c in text.chars() {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            if c == '\r' {
+                continue;
+            }
+            
+            // Re-lock per character so pointer events aren't starved while typing
+            let mut backend = self.backend.lock().unwrap();
+            
+            match keys::char_to_key(c) {
+                Some((key, shift)) => {
+                    let modifiers: &[Key] = if shift { &[Key::KEY_LEFTSHIFT] } else { &[] };
+                    tap_key(&mut **backend, key, modifiers)?;
+                }
+                None => {
+                    // No direct keycode: fall back to Ctrl+Shift+U hex entry
+                    tap_key(&mut **backend, Key::KEY_U, &[Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT])?;
+                    for key in keys::unicode_hex_keys(c) {
+                        tap_key(&mut **backend, key, &[])?;
+                    }
+                }
+            }
+            
+            drop(backend);
+            
+            if !delay.is_zero() {
+                std::thread::sleep(delay);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Press and release `key` while holding `modifiers`.
+fn tap_key(
+    backend: &mut dyn InputBackend,
+    key: Key,
+    modifiers: &[Key],
+) -> std::io::Result<()> {
+    for modifier in modifiers {
+        backend.emit(Device::Keyboard, &[
+            InputEvent::new(EventType::KEY, modifier.0, 1),
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+        ])?;
+    }
+    
+    backend.emit(Device::Keyboard, &[
+        InputEvent::new(EventType::KEY, key.0, 1),
+        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+    ])?;
+    
+    backend.emit(Device::Keyboard, &[
+        InputEvent::new(EventType::KEY, key.0, 0),
+        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+    ])?;
+    
+    for modifier in modifiers.iter().rev() {
+        backend.emit(Device::Keyboard, &[
+            InputEvent::new(EventType::KEY, modifier.0, 0),
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+        ])?;
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYN: (EventType, u16, i32) = (EventType::SYNCHRONIZATION, 0, 0);
+
+    fn controller() -> (MouseController, RecordingBackend) {
+        let backend = RecordingBackend::default();
+        (MouseController::new(Box::new(backend.clone()), ScrollDirection::Natural), backend)
+    }
+
+    fn key(key: Key, value: i32) -> (EventType, u16, i32) {
+        (EventType::KEY, key.code(), value)
+    }
+
+    /// Keyboard groups flattened to (key, value), dropping the SYN_REPORTs.
+    fn keyboard_keys(backend: &RecordingBackend) -> Vec<(Key, i32)> {
+        backend
+            .groups()
+            .into_iter()
+            .filter(|(device, _)| *device == Device::Keyboard)
+            .flat_map(|(_, events)| events)
+            .filter(|(event_type, _, _)| *event_type == EventType::KEY)
+            .map(|(_, code, value)| (Key::new(code), value))
+            .collect()
+    }
+
+    fn tap(key: Key) -> [(Key, i32); 2] {
+        [(key, 1), (key, 0)]
+    }
+
+    #[test]
+    fn move_emits_relative_motion_in_one_group() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::Move { dx: 3.7, dy: -2.0, t: None }, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![(
+                Device::Pointer,
+                vec![
+                    (EventType::RELATIVE, RelativeAxisType::REL_X.0, 3),
+                    (EventType::RELATIVE, RelativeAxisType::REL_Y.0, -2),
+                    SYN,
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn click_presses_and_releases_button() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::Click { button: "right".into() }, &mut Remainder::default()).unwrap();
+        let error = controller
+            .handle_event(TrackpadEvent::Click { button: "bogus".into() }, &mut Remainder::default())
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "Unknown button: bogus");
+        assert_eq!(
+            backend.groups(),
+            vec![
+                (Device::Pointer, vec![key(Key::BTN_RIGHT, 1), SYN]),
+                (Device::Pointer, vec![key(Key::BTN_RIGHT, 0), SYN]),
+            ]
+        );
+    }
+
+    #[test]
+    fn scroll_uses_natural_direction() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::Scroll { dx: 20.0, dy: 30.0 }, &mut Remainder::default()).unwrap();
+        // Less than a click sends nothing, not even a SYN_REPORT
+        controller.handle_event(TrackpadEvent::Scroll { dx: 0.0, dy: 0.05 }, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![(
+                Device::Pointer,
+                vec![
+                    (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, 360),
+                    (EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, 3),
+                    (EventType::RELATIVE, RelativeAxisType::REL_HWHEEL_HI_RES.0, -240),
+                    (EventType::RELATIVE, RelativeAxisType::REL_HWHEEL.0, -2),
+                    SYN,
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn traditional_scroll_follows_the_wheel() {
+        let backend = RecordingBackend::default();
+        let controller = MouseController::new(Box::new(backend.clone()), ScrollDirection::Traditional);
+        controller.handle_event(TrackpadEvent::Scroll { dx: 10.0, dy: 10.0 }, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![(
+                Device::Pointer,
+                vec![
+                    (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, -120),
+                    (EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, -1),
+                    (EventType::RELATIVE, RelativeAxisType::REL_HWHEEL_HI_RES.0, 120),
+                    (EventType::RELATIVE, RelativeAxisType::REL_HWHEEL.0, 1),
+                    SYN,
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn slow_scrolling_is_smooth_and_clicks_stay_in_step() {
+        let (controller, backend) = controller();
+        let mut remainder = Remainder::default();
+        // A quarter click down, four times, then back up a bit
+        for dy in [2.5, 2.5, 2.5, 2.5, 0.04, -1.0] {
+            controller.handle_event(TrackpadEvent::Scroll { dx: 0.0, dy }, &mut remainder).unwrap();
+        }
+
+        let hi_res = |value| (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, value);
+        assert_eq!(
+            backend.groups(),
+            vec![
+                (Device::Pointer, vec![hi_res(30), SYN]),
+                (Device::Pointer, vec![hi_res(30), SYN]),
+                (Device::Pointer, vec![hi_res(30), SYN]),
+                (Device::Pointer, vec![hi_res(30), (EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, 1), SYN]),
+                // The tiny scroll's 0.48 of a unit counts towards the way back
+                (Device::Pointer, vec![hi_res(-11), SYN]),
+            ]
+        );
+        assert_eq!(remainder.wheel.notch, -11);
+    }
+
+    #[test]
+    fn huge_scrolls_saturate() {
+        let (controller, backend) = controller();
+        let mut remainder = Remainder::default();
+        for _ in 0..2 {
+            controller.handle_event(TrackpadEvent::Scroll { dx: 0.0, dy: 1e12 }, &mut remainder).unwrap();
+        }
+        controller.handle_event(TrackpadEvent::Scroll { dx: 0.0, dy: 10.0 }, &mut remainder).unwrap();
+
+        let groups = backend.groups();
+        assert_eq!(groups.len(), 3);
+        for (_, events) in &groups[..2] {
+            assert_eq!(events[0], (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, i32::MAX));
+        }
+        assert_eq!(groups[2].1[0], (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, 120));
+    }
+
+    #[test]
+    fn sub_pixel_motion_adds_up() {
+        let (controller, backend) = controller();
+        let mut remainder = Remainder::default();
+        for _ in 0..4 {
+            controller.handle_event(TrackpadEvent::Move { dx: 0.3, dy: -0.3, t: None }, &mut remainder).unwrap();
+        }
+
+        // 0.3 + 0.3 + 0.3 first makes a pixel, leaving 0.2 for the fourth
+        assert_eq!(
+            backend.groups(),
+            vec![
+                (
+                    Device::Pointer,
+                    vec![
+                        (EventType::RELATIVE, RelativeAxisType::REL_X.0, 1),
+                        (EventType::RELATIVE, RelativeAxisType::REL_Y.0, -1),
+                        SYN,
+                    ]
+                ),
+            ]
+        );
+        assert!((remainder.x - 0.2).abs() < 1e-9);
+        assert!((remainder.y + 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn batch_is_one_group() {
+        let (controller, backend) = controller();
+        let batch: TrackpadEvent = serde_json::from_str(
+            r#"{"type":"batch","events":[
+                {"dx":1.5,"dy":0.5,"t":10},
+                {"dx":1.0,"dy":0.75,"t":18},
+                {"kind":"scroll","dx":0,"dy":-25,"t":18}
+            ]}"#,
+        )
+        .unwrap();
+        controller.handle_event(batch, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![(
+                Device::Pointer,
+                vec![
+                    (EventType::RELATIVE, RelativeAxisType::REL_X.0, 2),
+                    (EventType::RELATIVE, RelativeAxisType::REL_Y.0, 1),
+                    (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, -300),
+                    (EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, -2),
+                    SYN,
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_batches() {
+        let (controller, backend) = controller();
+        let delta = |t| MotionDelta { kind: MotionKind::Move, dx: 5.0, dy: 0.0, t: Some(t) };
+
+        let backwards = TrackpadEvent::Batch { events: vec![delta(20.0), delta(10.0)] };
+        let error = controller.handle_event(backwards, &mut Remainder::default()).unwrap_err();
+        assert_eq!(error.to_string(), "Batch timestamps go backwards");
+
+        let long = TrackpadEvent::Batch { events: (0..=MAX_BATCH_LEN).map(|t| delta(t as f64)).collect() };
+        assert!(controller.handle_event(long, &mut Remainder::default()).is_err());
+
+        assert!(backend.groups().is_empty());
+    }
+
+    #[test]
+    fn overflowing_motion_leaves_the_remainder_usable() {
+        let (controller, backend) = controller();
+        let mut remainder = Remainder::default();
+        let huge = MotionDelta { kind: MotionKind::Move, dx: f64::MAX, dy: f64::MAX, t: None };
+
+        let batch = TrackpadEvent::Batch { events: vec![huge.clone(), huge] };
+        let error = controller.handle_event(batch, &mut remainder).unwrap_err();
+        assert_eq!(error.to_string(), "Batch adds up to too much motion");
+        assert!(backend.groups().is_empty());
+
+        // Finite, but not once turned into high-resolution wheel units
+        controller.handle_event(TrackpadEvent::Scroll { dx: 0.0, dy: f64::MAX }, &mut remainder).unwrap();
+        controller.handle_event(TrackpadEvent::Scroll { dx: 0.0, dy: 10.0 }, &mut remainder).unwrap();
+        controller.handle_event(TrackpadEvent::Move { dx: 0.0, dy: 2.0, t: None }, &mut remainder).unwrap();
+        let groups = backend.groups();
+        assert_eq!(groups[groups.len() - 2].1[0], (EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, 120));
+        assert_eq!(groups[groups.len() - 1].1[0], (EventType::RELATIVE, RelativeAxisType::REL_Y.0, 2));
+    }
+
+    #[test]
+    fn drag_holds_left_button() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::DragStart, &mut Remainder::default()).unwrap();
+        controller.handle_event(TrackpadEvent::DragEnd, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![
+                (Device::Pointer, vec![key(Key::BTN_LEFT, 1), SYN]),
+                (Device::Pointer, vec![key(Key::BTN_LEFT, 0), SYN]),
+            ]
+        );
+    }
+
+    #[test]
+    fn swipe_sends_alt_arrow() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::Swipe { direction: "left".into() }, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![
+                (Device::Pointer, vec![key(Key::KEY_LEFTALT, 1), SYN]),
+                (Device::Pointer, vec![key(Key::KEY_LEFT, 1), SYN]),
+                (Device::Pointer, vec![key(Key::KEY_LEFT, 0), SYN]),
+                (Device::Pointer, vec![key(Key::KEY_LEFTALT, 0), SYN]),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrow_key_taps_key() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::ArrowKey { key: "up".into() }, &mut Remainder::default()).unwrap();
+
+        assert_eq!(
+            backend.groups(),
+            vec![
+                (Device::Pointer, vec![key(Key::KEY_UP, 1), SYN]),
+                (Device::Pointer, vec![key(Key::KEY_UP, 0), SYN]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_directions_are_rejected() {
+        let (controller, backend) = controller();
+        assert!(controller.handle_event(TrackpadEvent::Swipe { direction: "up".into() }, &mut Remainder::default()).is_err());
+        assert!(controller.handle_event(TrackpadEvent::ArrowKey { key: "sideways".into() }, &mut Remainder::default()).is_err());
+        assert!(backend.groups().is_empty());
+    }
+
+    #[test]
+    fn key_tap_wraps_modifiers_around_key() {
+        let (controller, backend) = controller();
+        controller
+            .handle_event(TrackpadEvent::Key {
+                key: "t".into(),
+                action: KeyAction::Tap,
+                modifiers: vec!["ctrl".into(), "Shift".into()],
+            }, &mut Remainder::default())
+            .unwrap();
+
+        assert_eq!(
+            keyboard_keys(&backend),
+            vec![
+                (Key::KEY_LEFTCTRL, 1),
+                (Key::KEY_LEFTSHIFT, 1),
+                (Key::KEY_T, 1),
+                (Key::KEY_T, 0),
+                (Key::KEY_LEFTSHIFT, 0),
+                (Key::KEY_LEFTCTRL, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_press_and_release_are_separate() {
+        let (controller, backend) = controller();
+        controller
+            .handle_event(TrackpadEvent::Key {
+                key: "KEY_ENTER".into(),
+                action: KeyAction::Press,
+                modifiers: vec![],
+            }, &mut Remainder::default())
+            .unwrap();
+        controller
+            .handle_event(TrackpadEvent::Key {
+                key: "return".into(),
+                action: KeyAction::Release,
+                modifiers: vec![],
+            }, &mut Remainder::default())
+            .unwrap();
+
+        assert_eq!(keyboard_keys(&backend), tap(Key::KEY_ENTER));
+    }
+
+    #[test]
+    fn unknown_key_emits_nothing() {
+        let (controller, backend) = controller();
+        let result = controller.handle_event(TrackpadEvent::Key {
+            key: "a".into(),
+            action: KeyAction::Tap,
+            modifiers: vec!["hyper".into()],
+        }, &mut Remainder::default());
+
+        assert_eq!(result.unwrap_err().to_string(), "Unknown key: hyper");
+        assert!(backend.groups().is_empty());
+    }
+
+    #[test]
+    fn type_text_holds_shift_for_uppercase_and_symbols() {
+        let (controller, backend) = controller();
+        controller
+            .handle_event(TrackpadEvent::TypeText { text: "aB!\r\n".into(), delay_ms: Some(0) }, &mut Remainder::default())
+            .unwrap();
+
+        let mut expected = tap(Key::KEY_A).to_vec();
+        expected.extend([(Key::KEY_LEFTSHIFT, 1)]);
+        expected.extend(tap(Key::KEY_B));
+        expected.extend([(Key::KEY_LEFTSHIFT, 0), (Key::KEY_LEFTSHIFT, 1)]);
+        expected.extend(tap(Key::KEY_1));
+        expected.extend([(Key::KEY_LEFTSHIFT, 0)]);
+        expected.extend(tap(Key::KEY_ENTER));
+        assert_eq!(keyboard_keys(&backend), expected);
+    }
+
+    #[test]
+    fn type_text_falls_back_to_unicode_hex_entry() {
+        let (controller, backend) = controller();
+        controller
+            .handle_event(TrackpadEvent::TypeText { text: "é".into(), delay_ms: Some(0) }, &mut Remainder::default())
+            .unwrap();
+
+        let mut expected = vec![(Key::KEY_LEFTCTRL, 1), (Key::KEY_LEFTSHIFT, 1)];
+        expected.extend(tap(Key::KEY_U));
+        expected.extend([(Key::KEY_LEFTSHIFT, 0), (Key::KEY_LEFTCTRL, 0)]);
+        expected.extend(tap(Key::KEY_E));
+        expected.extend(tap(Key::KEY_9));
+        expected.extend(tap(Key::KEY_SPACE));
+        assert_eq!(keyboard_keys(&backend), expected);
+    }
+
+    #[test]
+    fn type_text_caps_how_long_it_takes() {
+        let (controller, backend) = controller();
+        let text = "a".repeat(61);
+        let result = controller.type_text(&text, Some(keys::MAX_TYPE_DELAY_MS), &AtomicBool::new(false));
+        assert!(result.unwrap_err().to_string().contains("too long to type"));
+        assert!(backend.groups().is_empty());
+    }
+
+    #[test]
+    fn type_text_stops_when_cancelled() {
+        let (controller, backend) = controller();
+        controller.type_text("abc", Some(0), &AtomicBool::new(true)).unwrap();
+        assert!(backend.groups().is_empty());
+    }
+
+    #[test]
+    fn clipboard_is_not_an_input_event() {
+        let (controller, backend) = controller();
+        controller.handle_event(TrackpadEvent::Clipboard { content: "hi".into() }, &mut Remainder::default()).unwrap();
+        assert!(backend.groups().is_empty());
+    }
+
+    #[test]
+    fn disabled_backend_reports_reason() {
+        let controller = MouseController::new(Box::new(DisabledBackend::new("no uinput")), ScrollDirection::Natural);
+        let error = controller.handle_event(TrackpadEvent::DragStart, &mut Remainder::default()).unwrap_err();
+        assert_eq!(error.to_string(), "Input devices unavailable: no uinput");
+    }
+}
@@ -0,0 +1,153 @@
+use evdev::{AttributeSet, Key};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// First code past the keyboard range; everything from here on is BTN_*.
+const KEYBOARD_KEY_END: u16 = 0x100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAction {
+    Press,
+    Release,
+    #[default]
+    Tap,
+}
+
+/// Every keyboard key the virtual keyboard advertises (KEY_ESC up to, but not
+/// including, the BTN_* range).
+pub fn keyboard_keys() -> AttributeSet<Key> {
+    let mut keys = AttributeSet::<Key>::new();
+    for code in Key::KEY_ESC.code()..KEYBOARD_KEY_END {
+        keys.insert(Key::new(code));
+    }
+    keys
+}
+
+/// Short names phones and humans tend to send, mapped to the evdev name.
+const ALIASES: &[(&str, &str)] = &[
+    ("CTRL", "KEY_LEFTCTRL"),
+    ("CONTROL", "KEY_LEFTCTRL"),
+    ("SHIFT", "KEY_LEFTSHIFT"),
+    ("ALT", "KEY_LEFTALT"),
+    ("OPTION", "KEY_LEFTALT"),
+    ("ALTGR", "KEY_RIGHTALT"),
+    ("META", "KEY_LEFTMETA"),
+    ("SUPER", "KEY_LEFTMETA"),
+    ("WIN", "KEY_LEFTMETA"),
+    ("CMD", "KEY_LEFTMETA"),
+    ("COMMAND", "KEY_LEFTMETA"),
+    ("ESCAPE", "KEY_ESC"),
+    ("RETURN", "KEY_ENTER"),
+    ("DEL", "KEY_DELETE"),
+    ("INS", "KEY_INSERT"),
+    ("BKSP", "KEY_BACKSPACE"),
+    ("PGUP", "KEY_PAGEUP"),
+    ("PGDN", "KEY_PAGEDOWN"),
+    ("PAGE_UP", "KEY_PAGEUP"),
+    ("PAGE_DOWN", "KEY_PAGEDOWN"),
+    ("ARROWUP", "KEY_UP"),
+    ("ARROWDOWN", "KEY_DOWN"),
+    ("ARROWLEFT", "KEY_LEFT"),
+    ("ARROWRIGHT", "KEY_RIGHT"),
+    ("PRINTSCREEN", "KEY_SYSRQ"),
+    ("PRTSC", "KEY_SYSRQ"),
+    ("CAPSLOCK", "KEY_CAPSLOCK"),
+    ("VOLUMEUP", "KEY_VOLUMEUP"),
+    ("VOLUMEDOWN", "KEY_VOLUMEDOWN"),
+    ("MUTE", "KEY_MUTE"),
+];
+
+/// Resolve a key name from the client into a keyboard key.
+///
+/// Accepts evdev names (`KEY_ENTER`), the same without the prefix in any case
+/// (`enter`, `Enter`, `f5`) and a handful of common aliases (`ctrl`, `esc`,
+/// `cmd`, `pgup`, ...).
+pub fn parse_key(name: &str) -> Result<Key, String> {
+    let normalized = name.trim().to_ascii_uppercase().replace(['-', ' '], "_");
+    if normalized.is_empty() {
+        return Err("Empty key name".to_string());
+    }
+
+    let evdev_name = match ALIASES.iter().find(|(alias, _)| *alias == normalized) {
+        Some((_, evdev_name)) => evdev_name.to_string(),
+        None if normalized.starts_with("KEY_") => normalized,
+        None => format!("KEY_{}", normalized),
+    };
+
+    match Key::from_str(&evdev_name) {
+        Ok(key) if key.code() > 0 && key.code() < KEYBOARD_KEY_END => Ok(key),
+        _ => Err(format!("Unknown key: {}", name)),
+    }
+}
+
+/// Default pause between characters of a `type_text` event.
+pub const DEFAULT_TYPE_DELAY_MS: u64 = 5;
+
+/// Upper bound on the client-supplied `type_text` delay.
+pub const MAX_TYPE_DELAY_MS: u64 = 1000;
+
+/// Longest `type_text` payload accepted in one event.
+pub const MAX_TYPE_TEXT_CHARS: usize = 10_000;
+
+/// Longest a single `type_text` event may take, characters times delay.
+pub const MAX_TYPE_DURATION_MS: u64 = 60_000;
+
+/// US layout punctuation: (unshifted, shifted, key).
+const US_SYMBOLS: &[(char, char, Key)] = &[
+    ('`', '~', Key::KEY_GRAVE),
+    ('1', '!', Key::KEY_1),
+    ('2', '@', Key::KEY_2),
+    ('3', '#', Key::KEY_3),
+    ('4', '$', Key::KEY_4),
+    ('5', '%', Key::KEY_5),
+    ('6', '^', Key::KEY_6),
+    ('7', '&', Key::KEY_7),
+    ('8', '*', Key::KEY_8),
+    ('9', '(', Key::KEY_9),
+    ('0', ')', Key::KEY_0),
+    ('-', '_', Key::KEY_MINUS),
+    ('=', '+', Key::KEY_EQUAL),
+    ('[', '{', Key::KEY_LEFTBRACE),
+    (']', '}', Key::KEY_RIGHTBRACE),
+    ('\\', '|', Key::KEY_BACKSLASH),
+    (';', ':', Key::KEY_SEMICOLON),
+    ('\'', '"', Key::KEY_APOSTROPHE),
+    (',', '<', Key::KEY_COMMA),
+    ('.', '>', Key::KEY_DOT),
+    ('/', '?', Key::KEY_SLASH),
+];
+
+/// Map a character to the US layout key that produces it and whether shift
+/// must be held. Returns `None` for anything the layout can't type directly.
+pub fn char_to_key(c: char) -> Option<(Key, bool)> {
+    match c {
+        ' ' => Some((Key::KEY_SPACE, false)),
+        '\n' => Some((Key::KEY_ENTER, false)),
+        '\t' => Some((Key::KEY_TAB, false)),
+        'a'..='z' | 'A'..='Z' => {
+            let key = Key::from_str(&format!("KEY_{}", c.to_ascii_uppercase())).ok()?;
+            Some((key, c.is_ascii_uppercase()))
+        }
+        _ => US_SYMBOLS.iter().find_map(|&(plain, shifted, key)| {
+            if c == plain {
+                Some((key, false))
+            } else if c == shifted {
+                Some((key, true))
+            } else {
+                None
+            }
+        }),
+    }
+}
+
+/// Keys to type after Ctrl+Shift+U to enter `c` by its hex code point
+/// (GTK/IBus unicode entry), including the terminating space.
+pub fn unicode_hex_keys(c: char) -> Vec<Key> {
+    format!("{:x}", c as u32)
+        .chars()
+        .filter_map(char_to_key)
+        .map(|(key, _)| key)
+        .chain(std::iter::once(Key::KEY_SPACE))
+        .collect()
+}
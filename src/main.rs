@@ -1,226 +1,119 @@
-use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, EventType, InputEvent, RelativeAxisType, Key};
+// The route table is one long chain of warp filters
+#![recursion_limit = "256"]
+
 use futures::{StreamExt, SinkExt};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use clap::Parser;
 use warp::{ws::Message, Filter, multipart::FormData};
 use tokio::sync::broadcast;
-use std::collections::HashMap;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 use bytes::Buf;
+use sha2::{Digest, Sha256};
+use warp::http::StatusCode;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-enum TrackpadEvent {
-    #[serde(rename = "move")]
-    Move { dx: f64, dy: f64 },
-    #[serde(rename = "click")]
-    Click { button: String },
-    #[serde(rename = "scroll")]
-    Scroll { dx: f64, dy: f64 },
-    #[serde(rename = "drag_start")]
-    DragStart,
-    #[serde(rename = "drag_end")]
-    DragEnd,
-    #[serde(rename = "swipe")]
-    Swipe { direction: String },
-    #[serde(rename = "arrow_key")]
-    ArrowKey { key: String },
-    #[serde(rename = "clipboard")]
-    Clipboard { content: String },
-}
+mod acceleration;
+mod archive;
+mod assets;
+mod auth;
+mod binary;
+mod clipboard;
+mod config;
+mod deliver;
+mod downloads;
+mod events;
+mod files;
+mod history;
+mod input;
+mod keys;
+mod protocol;
+mod quota;
+mod thumbnails;
+mod tls;
+mod uploads;
+
+use acceleration::Accelerator;
+use auth::{DeviceRegistry, SharedRegistry};
+use clipboard::{ArboardClipboard, ClipboardBridge};
+use config::{Cli, Config};
+use events::TrackpadEvent;
+use files::{FileInfo, FileStorage, FileStore};
+use history::{ClipboardHistory, SharedHistory};
+use input::{DisabledBackend, InputBackend, MouseController, Remainder, UinputBackend};
+use protocol::{Capabilities, ClientMessage, Encoding, ErrorCode, ServerMessage};
+use quota::{Quota, SharedQuota};
+use uploads::{SharedUploads, UploadSessions};
 
 #[derive(Debug, Clone, Serialize)]
 struct ClipboardItem {
+    id: String,
     content: String,
     timestamp: u64,
     source: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct FileInfo {
-    id: String,
-    filename: String,
-    size: u64,
-    uploaded_at: u64,
-}
-
-type FileStorage = Arc<Mutex<HashMap<String, FileInfo>>>;
-
-struct MouseController {
-    device: Arc<Mutex<evdev::uinput::VirtualDevice>>,
-}
-
-impl MouseController {
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let mut keys = AttributeSet::<Key>::new();
-        keys.insert(Key::BTN_LEFT);
-        keys.insert(Key::BTN_RIGHT);
-        keys.insert(Key::BTN_MIDDLE);
-        keys.insert(Key::KEY_LEFT);
-        keys.insert(Key::KEY_RIGHT);
-        keys.insert(Key::KEY_UP);
-        keys.insert(Key::KEY_DOWN);
-        keys.insert(Key::KEY_LEFTALT);
-        
-        let mut relative_axes = AttributeSet::<RelativeAxisType>::new();
-        relative_axes.insert(RelativeAxisType::REL_X);
-        relative_axes.insert(RelativeAxisType::REL_Y);
-        relative_axes.insert(RelativeAxisType::REL_WHEEL);
-        relative_axes.insert(RelativeAxisType::REL_HWHEEL);
-        
-        let device = VirtualDeviceBuilder::new()?
-            .name("Mobile Trackpad Virtual Mouse")
-            .with_keys(&keys)?
-            .with_relative_axes(&relative_axes)?
-            .build()?;
-        
-        Ok(Self {
-            device: Arc::new(Mutex::new(device)),
-        })
-    }
-
-    fn handle_event(&self, event: TrackpadEvent) -> Result<(), Box<dyn std::error::Error>> {
-        let mut device = self.device.lock().unwrap();
-        
-        match event {
-            TrackpadEvent::Move { dx, dy } => {
-                let events = vec![
-                    InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, dx as i32),
-                    InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, dy as i32),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ];
-                device.emit(&events)?;
-            }
-            TrackpadEvent::Click { button } => {
-                let key = match button.as_str() {
-                    "left" => Key::BTN_LEFT,
-                    "right" => Key::BTN_RIGHT,
-                    "middle" => Key::BTN_MIDDLE,
-                    _ => Key::BTN_LEFT,
-                };
-                
-                let events_down = vec![
-                    InputEvent::new(EventType::KEY, key.0, 1),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ];
-                device.emit(&events_down)?;
-                
-                let events_up = vec![
-                    InputEvent::new(EventType::KEY, key.0, 0),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ];
-                device.emit(&events_up)?;
-            }
-            TrackpadEvent::Scroll { dx, dy } => {
-                let mut events = Vec::new();
-                
-                // macOS-style natural scrolling: invert both directions
-                if dy.abs() > 0.1 {
-                    events.push(InputEvent::new(
-                        EventType::RELATIVE,
-                        RelativeAxisType::REL_WHEEL.0,
-                        (dy / 10.0) as i32,
-                    ));
-                }
-                
-                if dx.abs() > 0.1 {
-                    events.push(InputEvent::new(
-                        EventType::RELATIVE,
-                        RelativeAxisType::REL_HWHEEL.0,
-                        -(dx / 10.0) as i32,
-                    ));
-                }
-                
-                events.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
-                device.emit(&events)?;
-            }
-            TrackpadEvent::DragStart => {
-                let events = vec![
-                    InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 1),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ];
-                device.emit(&events)?;
-            }
-            TrackpadEvent::DragEnd => {
-                let events = vec![
-                    InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, 0),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ];
-                device.emit(&events)?;
-            }
-            TrackpadEvent::Swipe { direction } => {
-                let arrow_key = match direction.as_str() {
-                    "left" => Key::KEY_LEFT,
-                    "right" => Key::KEY_RIGHT,
-                    _ => return Ok(()),
-                };
-                
-                device.emit(&[
-                    InputEvent::new(EventType::KEY, Key::KEY_LEFTALT.0, 1),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ])?;
-                
-                device.emit(&[
-                    InputEvent::new(EventType::KEY, arrow_key.0, 1),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ])?;
-                
-                device.emit(&[
-                    InputEvent::new(EventType::KEY, arrow_key.0, 0),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ])?;
-                
-                device.emit(&[
-                    InputEvent::new(EventType::KEY, Key::KEY_LEFTALT.0, 0),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ])?;
-            }
-            TrackpadEvent::ArrowKey { key } => {
-                let arrow_key = match key.as_str() {
-                    "up" => Key::KEY_UP,
-                    "down" => Key::KEY_DOWN,
-                    "left" => Key::KEY_LEFT,
-                    "right" => Key::KEY_RIGHT,
-                    _ => return Ok(()),
-                };
-                
-                device.emit(&[
-                    InputEvent::new(EventType::KEY, arrow_key.0, 1),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ])?;
-                
-                device.emit(&[
-                    InputEvent::new(EventType::KEY, arrow_key.0, 0),
-                    InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                ])?;
-            }
-            TrackpadEvent::Clipboard { .. } => {
-                // Clipboard is handled separately in websocket handler
-                // This is a no-op for the mouse controller
-            }
+impl ClipboardItem {
+    fn new(content: String, source: &str) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            content,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            source: source.to_string(),
         }
-        
-        Ok(())
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_websocket(
     ws: warp::ws::WebSocket,
     mouse_controller: Arc<MouseController>,
     clipboard_tx: broadcast::Sender<ClipboardItem>,
+    clipboard_bridge: Option<Arc<ClipboardBridge>>,
+    history: SharedHistory,
+    registry: SharedRegistry,
+    file_storage: FileStorage,
+    config: Arc<Config>,
+    device_id: String,
 ) {
     let (mut ws_tx, mut ws_rx) = ws.split();
 
-    let msg = serde_json::json!({
-        "type": "connected",
-        "message": "Trackpad connected successfully"
-    });
-    let _ = ws_tx.send(Message::text(msg.to_string())).await;
+    // Tell the client what it can send before anything else
+    let profiles = config.pointer.all_profiles();
+    let capabilities = Capabilities::new(
+        mouse_controller.devices(),
+        clipboard_bridge.is_some(),
+        profiles.keys().cloned().collect(),
+    );
+    let hello = ServerMessage::hello(capabilities.clone());
+    if ws_tx.send(Message::text(hello.to_json())).await.is_err() {
+        return;
+    }
 
-    // Subscribe to clipboard broadcasts
+    // Subscribe to clipboard broadcasts and file list changes
     let mut clipboard_rx = clipboard_tx.subscribe();
+    let mut file_rx = file_storage.subscribe();
+    
+    // Replay the stored history so late joiners see earlier items
+    for entry in history.entries() {
+        let msg = serde_json::json!({
+            "type": "clipboard_history",
+            "id": entry.id,
+            "content": entry.content,
+            "timestamp": entry.timestamp,
+            "source": entry.source,
+            "pinned": entry.pinned
+        });
+        if ws_tx.send(Message::text(msg.to_string())).await.is_err() {
+            return;
+        }
+    }
+    
     let ws_tx = Arc::new(tokio::sync::Mutex::new(ws_tx));
     let ws_tx_clone = ws_tx.clone();
     
@@ -229,6 +122,7 @@ async fn handle_websocket(
         while let Ok(item) = clipboard_rx.recv().await {
             let msg = serde_json::json!({
                 "type": "clipboard_history",
+                "id": item.id,
                 "content": item.content,
                 "timestamp": item.timestamp,
                 "source": item.source
@@ -241,316 +135,819 @@ async fn handle_websocket(
         }
     });
 
-    while let Some(result) = ws_rx.next().await {
-        match result {
-            Ok(msg) => {
-                if let Ok(text) = msg.to_str() {
-                    if let Ok(event) = serde_json::from_str::<TrackpadEvent>(text) {
-                        // Handle clipboard separately
-                        if let TrackpadEvent::Clipboard { content } = &event {
-                            // Broadcast to all connected clients
-                            let item = ClipboardItem {
-                                content: content.clone(),
-                                timestamp: std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                                source: "Client".to_string(),
-                            };
-                            let _ = clipboard_tx.send(item);
-                        } else {
-                            // Handle other events through mouse controller
-                            if let Err(e) = mouse_controller.handle_event(event) {
-                                eprintln!("Error handling event: {}", e);
-                            }
-                        }
+    // Task to forward file list changes so the files page updates live
+    let ws_tx_files = ws_tx.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match file_rx.recv().await {
+                Ok(event) => event,
+                // Missed some; the client refetches the list on `files_changed`
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let msg = serde_json::json!({"type": "files_changed"});
+                    if ws_tx_files.lock().await.send(Message::text(msg.to_string())).await.is_err() {
+                        break;
                     }
+                    continue;
                 }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let msg = serde_json::to_string(&event).unwrap();
+            let mut tx = ws_tx_files.lock().await;
+            if tx.send(Message::text(msg)).await.is_err() {
+                break; // Connection closed
             }
+        }
+    });
+
+    // Until the client's hello says otherwise
+    let mut encoding = Encoding::Json;
+    // Motion too small to send yet, carried over to the next event
+    let mut remainder = Remainder::default();
+    let mut accelerator = Accelerator::new(profiles[&config.pointer.profile].clone());
+    let connected_at = std::time::Instant::now();
+    // The text being typed, if any, and a flag to stop it when the client goes
+    let mut typing: Option<tokio::task::JoinHandle<()>> = None;
+    let stop_typing = Arc::new(AtomicBool::new(false));
+    
+    while let Some(result) = ws_rx.next().await {
+        // Drop the connection as soon as the device is revoked
+        if !registry.is_paired(&device_id) {
+            println!("🚫 Closing connection of revoked device {}", device_id);
+            let _ = ws_tx.lock().await.close().await;
+            break;
+        }
+        
+        let msg = match result {
+            Ok(msg) => msg,
             Err(e) => {
                 eprintln!("WebSocket error: {}", e);
                 break;
             }
+        };
+        let parsed = if let Ok(text) = msg.to_str() {
+            protocol::parse(text, &capabilities)
+        } else if msg.is_binary() {
+            protocol::parse_binary(msg.as_bytes(), encoding, &capabilities).map(ClientMessage::Event)
+        } else {
+            // Pings, pongs and closes are handled by warp
+            continue;
+        };
+        
+        let mut event = match parsed {
+            Ok(ClientMessage::Event(event)) => event,
+            Ok(ClientMessage::Hello { version, encoding: requested, profile }) => {
+                encoding = requested;
+                let profile = profile.unwrap_or_else(|| config.pointer.profile.clone());
+                accelerator = Accelerator::new(profiles[&profile].clone());
+                let reply = ServerMessage::Ready { protocol_version: version, encoding, profile };
+                let _ = ws_tx.lock().await.send(Message::text(reply.to_json())).await;
+                continue;
+            }
+            Err(reply) => {
+                let refused_version = matches!(reply, ServerMessage::Error { code: ErrorCode::UnsupportedVersion, .. });
+                let mut tx = ws_tx.lock().await;
+                let _ = tx.send(Message::text(reply.to_json())).await;
+                // Nothing it sends could be understood
+                if refused_version {
+                    let _ = tx.close().await;
+                    break;
+                }
+                continue;
+            }
+        };
+        
+        // Handle clipboard separately
+        if let TrackpadEvent::Clipboard { content } = &event {
+            // Put it on the desktop clipboard too
+            if let Some(bridge) = &clipboard_bridge {
+                if let Err(e) = tokio::task::block_in_place(|| bridge.set_from_client(content)) {
+                    eprintln!("Error writing host clipboard: {}", e);
+                }
+            }
+            
+            // Broadcast to all connected clients
+            let _ = clipboard_tx.send(ClipboardItem::new(content.clone(), "Client"));
+            continue;
+        }
+        
+        // Handle other events through mouse controller
+        let event_type = event.event_type();
+        accelerator.accelerate(&mut event, connected_at.elapsed().as_secs_f64() * 1000.0);
+        // Typing sleeps between keys, so it gets a thread of its own and
+        // this loop carries on; one text at a time per connection
+        if let TrackpadEvent::TypeText { text, delay_ms } = event {
+            if typing.as_ref().is_some_and(|task| !task.is_finished()) {
+                let reply = ServerMessage::error(ErrorCode::EventFailed, "Still typing the previous text", Some(event_type));
+                let _ = ws_tx.lock().await.send(Message::text(reply.to_json())).await;
+                continue;
+            }
+            let controller = mouse_controller.clone();
+            let stop = stop_typing.clone();
+            let ws_tx = ws_tx.clone();
+            typing = Some(tokio::spawn(async move {
+                let typed = tokio::task::spawn_blocking(move || {
+                    controller.type_text(&text, delay_ms, &stop).map_err(|e| e.to_string())
+                })
+                .await;
+                if let Ok(Err(error)) = typed {
+                    eprintln!("Error handling event: {}", error);
+                    let reply = ServerMessage::error(ErrorCode::EventFailed, error, Some(event_type));
+                    let _ = ws_tx.lock().await.send(Message::text(reply.to_json())).await;
+                }
+            }));
+            continue;
+        }
+        
+        let error = mouse_controller.handle_event(event, &mut remainder).err().map(|e| e.to_string());
+        if let Some(error) = error {
+            eprintln!("Error handling event: {}", error);
+            
+            // Let the client know why nothing happened
+            let reply = ServerMessage::error(ErrorCode::EventFailed, error, Some(event_type));
+            let mut tx = ws_tx.lock().await;
+            let _ = tx.send(Message::text(reply.to_json())).await;
         }
     }
+    stop_typing.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadQuery {
+    /// Hex SHA-256 the client expects the file to have.
+    #[serde(default)]
+    sha256: Option<String>,
 }
 
 async fn handle_upload(
+    query: UploadQuery,
+    content_length: u64,
     mut form: FormData,
     file_storage: FileStorage,
+    quota: SharedQuota,
     clipboard_tx: broadcast::Sender<ClipboardItem>,
+    config: Arc<Config>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let expected_sha256 = match query.sha256.as_deref().map(files::normalize_sha256) {
+        Some(None) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": "Invalid sha256"})),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        Some(Some(digest)) => Some(digest),
+        None => None,
+    };
+    
+    // Held until the upload is done; the body is a little bigger than the file
+    let mut reservation = match tokio::task::block_in_place(|| quota.reserve(content_length)) {
+        Ok(reservation) => reservation,
+        Err(e) => {
+            println!("🚫 Refused upload: {}", e);
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                StatusCode::INSUFFICIENT_STORAGE,
+            ));
+        }
+    };
+    
     // Process parts as they arrive, don't collect into memory
-    while let Some(Ok(mut part)) = form.next().await {
-        if part.name() == "file" {
-            let filename = part.filename().unwrap_or("unnamed").to_string();
-            let id = Uuid::new_v4().to_string();
-            let uploaded_at = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            
-            println!("📤 Starting upload: {}", filename);
-            
-            // Create uploads directory if it doesn't exist
-            fs::create_dir_all("./uploads").await.ok();
-            
-            let file_path = format!("./uploads/{}", id);
-            let mut file = fs::File::create(&file_path).await.unwrap();
-            
-            // Stream file data directly to disk as it arrives
-            let mut size = 0u64;
-            let mut last_log = 0u64;
-            while let Some(content) = part.data().await {
-                match content {
-                    Ok(chunk) => {
-                        let bytes = chunk.chunk();
-                        file.write_all(bytes).await.unwrap();
-                        size += bytes.len() as u64;
-                        
-                        // Log progress every 100KB
-                        if size - last_log > 100_000 {
-                            println!("  Progress: {} KB", size / 1024);
-                            last_log = size;
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("❌ Error reading chunk: {}", e);
-                        return Ok(warp::reply::json(&serde_json::json!({
-                            "error": "Upload failed"
-                        })));
+    let mut uploaded = Vec::new();
+    let mut files_seen = 0;
+    // From a `sha256` field, for the `file` part right after it
+    let mut part_sha256 = None;
+    while let Some(Ok(part)) = form.next().await {
+        match part.name() {
+            "sha256" => {
+                match read_field(part).await.as_deref().and_then(files::normalize_sha256) {
+                    Some(digest) => part_sha256 = Some(digest),
+                    None => {
+                        let error = serde_json::json!({"error": "Invalid sha256"});
+                        return Ok(upload_error(StatusCode::BAD_REQUEST, error, &uploaded));
                     }
                 }
+                continue;
             }
-            
-            // Ensure all data is written to disk
-            file.flush().await.unwrap();
-            drop(file);
-            
-            println!("✅ Upload complete: {} ({} KB)", filename, size / 1024);
-            
-            let file_info = FileInfo {
-                id: id.clone(),
-                filename: filename.clone(),
-                size,
-                uploaded_at,
-            };
-            
-            file_storage.lock().unwrap().insert(id.clone(), file_info.clone());
-            
-            // Notify all clients
-            let _ = clipboard_tx.send(ClipboardItem {
-                content: format!("File uploaded: {}", filename),
-                timestamp: uploaded_at,
-                source: "System".to_string(),
+            "file" => {}
+            _ => continue,
+        }
+        files_seen += 1;
+        if expected_sha256.is_some() && files_seen > 1 {
+            let error = serde_json::json!({
+                "error": "?sha256= only covers a single file; send a sha256 field before each file instead"
             });
-            
-            return Ok(warp::reply::json(&serde_json::json!({
-                "id": id,
-                "filename": filename
-            })));
+            return Ok(upload_error(StatusCode::BAD_REQUEST, error, &uploaded));
+        }
+        // The reservation came with a slot for the first file
+        if files_seen > 1 {
+            if let Err(e) = tokio::task::block_in_place(|| reservation.next_file()) {
+                println!("🚫 Refused the rest of an upload: {}", e);
+                let error = serde_json::json!({"error": e.to_string()});
+                return Ok(upload_error(StatusCode::INSUFFICIENT_STORAGE, error, &uploaded));
+            }
+        }
+        let expected = part_sha256.take().or_else(|| expected_sha256.clone());
+        match receive_file(part, expected.as_deref(), &file_storage, &clipboard_tx, &config).await {
+            Ok(file) => {
+                reservation.stored(file["size"].as_u64().unwrap_or(0));
+                uploaded.push(file);
+            }
+            Err((status, error)) => return Ok(upload_error(status, error, &uploaded)),
         }
     }
     
-    Ok(warp::reply::json(&serde_json::json!({"error": "No file uploaded"})))
+    if uploaded.is_empty() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "No file uploaded"})),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    Ok(warp::reply::with_status(warp::reply::json(&uploaded), StatusCode::OK))
 }
 
-async fn cleanup_old_files(file_storage: FileStorage) {
+/// Fail an upload part way. Files from the parts before stay stored, so
+/// they are listed under `uploaded` for the client to keep track of.
+fn upload_error(
+    status: StatusCode,
+    mut error: serde_json::Value,
+    uploaded: &[serde_json::Value],
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    error["uploaded"] = serde_json::json!(uploaded);
+    warp::reply::with_status(warp::reply::json(&error), status)
+}
+
+/// The value of a small text field, such as a digest.
+async fn read_field(mut part: warp::multipart::Part) -> Option<String> {
+    const MAX_FIELD_LEN: usize = 1024;
+    let mut value = Vec::new();
+    while let Some(chunk) = part.data().await {
+        value.extend_from_slice(chunk.ok()?.chunk());
+        if value.len() > MAX_FIELD_LEN {
+            return None;
+        }
+    }
+    String::from_utf8(value).ok()
+}
+
+/// Stream one `file` part to disk and store it, returning its `FileInfo`
+/// plus the `path` it was delivered to, or the status and error to fail the
+/// request with.
+async fn receive_file(
+    mut part: warp::multipart::Part,
+    expected_sha256: Option<&str>,
+    file_storage: &FileStorage,
+    clipboard_tx: &broadcast::Sender<ClipboardItem>,
+    config: &Config,
+) -> Result<serde_json::Value, (StatusCode, serde_json::Value)> {
+    let filename = files::sanitize_filename(part.filename().unwrap_or_default());
+    let id = Uuid::new_v4().to_string();
+    let uploaded_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    
+    println!("📤 Starting upload: {}", filename);
+    
+    // Create uploads directory if it doesn't exist
+    fs::create_dir_all(file_storage.uploads_dir()).await.ok();
+    
+    let file_path = file_storage.path(&id);
+    let mut file = fs::File::create(&file_path).await.unwrap();
+    
+    // Stream file data directly to disk as it arrives, hashing on the way
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    let mut last_log = 0u64;
+    while let Some(content) = part.data().await {
+        match content {
+            Ok(chunk) => {
+                let bytes = chunk.chunk();
+                file.write_all(bytes).await.unwrap();
+                hasher.update(bytes);
+                size += bytes.len() as u64;
+                
+                // Log progress every 100KB
+                if size - last_log > 100_000 {
+                    println!("  Progress: {} KB", size / 1024);
+                    last_log = size;
+                }
+            }
+            Err(e) => {
+                eprintln!("❌ Error reading chunk: {}", e);
+                drop(file);
+                fs::remove_file(&file_path).await.ok();
+                return Err((StatusCode::BAD_REQUEST, serde_json::json!({"error": "Upload failed"})));
+            }
+        }
+    }
+    
+    // Ensure all data is written to disk
+    file.flush().await.unwrap();
+    drop(file);
+    
+    let sha256 = format!("{:x}", hasher.finalize());
+    if expected_sha256.is_some_and(|expected| expected != sha256) {
+        eprintln!("❌ Upload of {} corrupted: SHA-256 is {}", filename, sha256);
+        fs::remove_file(&file_path).await.ok();
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            serde_json::json!({
+                "error": "SHA-256 mismatch",
+                "filename": filename,
+                "sha256": sha256
+            }),
+        ));
+    }
+    
+    println!("✅ Upload complete: {} ({} KB)", filename, size / 1024);
+    
+    let file_info = FileInfo {
+        id,
+        filename,
+        size,
+        uploaded_at,
+        expires_at: uploaded_at + config.file_ttl_secs,
+        sha256: Some(sha256),
+        pinned: false,
+        host_path: None,
+        mime: None,
+        width: None,
+        height: None,
+        thumbnail: false,
+    };
+    
+    // Delivering may copy the whole file to another filesystem
+    let stored = tokio::task::block_in_place(|| {
+        deliver::store_upload(file_info.clone(), file_storage, &config.deliver).inspect_err(|_| {
+            // It is listed in memory only; drop it rather than lose it on restart
+            file_storage.remove(&file_info.id).ok();
+        })
+    });
+    let delivered = match stored {
+        Ok(delivered) => delivered,
+        Err(e) => {
+            eprintln!("❌ Error saving file index: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                serde_json::json!({"error": "Failed to save file index", "filename": file_info.filename}),
+            ));
+        }
+    };
+    
+    thumbnails::spawn(file_storage.clone(), file_info.id.clone());
+    
+    // Notify all clients, with where the file landed on this computer
+    let message = match &delivered {
+        Some(path) => format!("File saved to {}", path.display()),
+        None => format!("File uploaded: {}", file_info.filename),
+    };
+    let _ = clipboard_tx.send(ClipboardItem::new(message, "System"));
+    
+    let mut reply = serde_json::to_value(&file_info).unwrap();
+    reply["path"] = serde_json::json!(delivered);
+    Ok(reply)
+}
+
+async fn cleanup_old_files(file_storage: FileStorage, uploads: SharedUploads, config: Arc<Config>) {
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+        tokio::time::sleep(tokio::time::Duration::from_secs(config.cleanup_interval_secs)).await;
         
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
         
-        let expired: Vec<String> = {
-            let storage = file_storage.lock().unwrap();
-            storage
-                .iter()
-                .filter(|(_, info)| now - info.uploaded_at > 3600)
-                .map(|(id, _)| id.clone())
-                .collect()
-        };
-        
-        for id in expired {
-            file_storage.lock().unwrap().remove(&id);
-            let file_path = format!("./uploads/{}", id);
-            fs::remove_file(file_path).await.ok();
-        }
+        // Both rewrite their index and delete files
+        tokio::task::block_in_place(|| {
+            if let Err(e) = file_storage.remove_expired(now) {
+                eprintln!("❌ Error saving file index: {}", e);
+            }
+            if let Err(e) = uploads.remove_expired(now) {
+                eprintln!("❌ Error saving upload sessions: {}", e);
+            }
+        });
     }
 }
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
     println!("🚀 Starting Mobile Trackpad Service...");
 
-    let mouse_controller = Arc::new(
-        MouseController::new()
-            .expect("Failed to create mouse controller. Make sure /dev/uinput is accessible.")
-    );
-    println!("✓ Mouse controller initialized (using evdev/uinput for Wayland)");
+    let backend: Box<dyn InputBackend> = match UinputBackend::new() {
+        Ok(backend) => {
+            println!("✓ Mouse controller initialized (using evdev/uinput for Wayland)");
+            Box::new(backend)
+        }
+        Err(e) => {
+            eprintln!("⚠️  Failed to create input devices: {}", e);
+            eprintln!("   Make sure /dev/uinput is accessible. Pointer and keyboard events will be rejected.");
+            Box::new(DisabledBackend::new(e.to_string()))
+        }
+    };
+    let mouse_controller = Arc::new(MouseController::new(backend, config.pointer.scroll_direction));
 
-    // Create file storage
-    let file_storage: FileStorage = Arc::new(Mutex::new(HashMap::new()));
+    // Load the uploaded files index and sync it with what's on disk
+    let file_storage: FileStorage = Arc::new(
+        FileStore::load(config.files_index_path(), &config.uploads_dir).unwrap_or_else(|e| {
+            eprintln!("⚠️  Failed to load file index, starting empty: {}", e);
+            FileStore::empty(config.files_index_path(), &config.uploads_dir)
+        })
+    );
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if let Err(e) = file_storage.reconcile(now) {
+        eprintln!("⚠️  Failed to clean up uploads directory: {}", e);
+    }
+    
+    // Resumable uploads survive restarts too
+    let uploads: SharedUploads = Arc::new(
+        UploadSessions::load(config.upload_sessions_path(), config.partial_uploads_dir(), config.upload_session_ttl_secs)
+            .unwrap_or_else(|e| {
+                eprintln!("⚠️  Failed to load upload sessions, starting empty: {}", e);
+                UploadSessions::empty(config.upload_sessions_path(), config.partial_uploads_dir(), config.upload_session_ttl_secs)
+            })
+    );
+    match uploads.reconcile(now) {
+        // Uploads whose last chunk arrived just before a shutdown
+        Ok(finished) => {
+            for session in finished {
+                if let Err(e) = uploads::finalize(&session, &uploads, &file_storage, &config, now) {
+                    eprintln!("⚠️  Failed to finish upload of {}: {}", session.filename, e);
+                }
+            }
+        }
+        Err(e) => eprintln!("⚠️  Failed to clean up partial uploads: {}", e),
+    }
+    
+    // Types, sizes and thumbnails of files stored before they were recorded
+    thumbnails::spawn_backfill(file_storage.clone());
+    
+    // Uploads are refused (or old files evicted) before the disk fills up
+    let quota: SharedQuota = Arc::new(Quota::new(config.quota.clone(), file_storage.clone(), uploads.clone()));
     
     // Spawn cleanup task
     let file_storage_cleanup = file_storage.clone();
+    let uploads_cleanup = uploads.clone();
+    let config_cleanup = config.clone();
     tokio::spawn(async move {
-        cleanup_old_files(file_storage_cleanup).await;
+        cleanup_old_files(file_storage_cleanup, uploads_cleanup, config_cleanup).await;
     });
 
     // Create broadcast channel for clipboard events
     let (clipboard_tx, _) = broadcast::channel::<ClipboardItem>(100);
 
+    // Load clipboard history and keep recording new items
+    let history: SharedHistory = Arc::new(
+        ClipboardHistory::load(config.history_path(), config.clipboard_history_size)
+            .unwrap_or_else(|e| {
+                eprintln!("⚠️  Failed to load clipboard history, starting empty: {}", e);
+                ClipboardHistory::empty(config.history_path(), config.clipboard_history_size)
+            })
+    );
+    tokio::spawn(history::record_history(history.clone(), clipboard_tx.clone()));
+
+    // Bridge the desktop clipboard, if there is one (headless boxes have none)
+    let clipboard_bridge = match ArboardClipboard::new() {
+        Ok(host_clipboard) => {
+            let bridge = Arc::new(ClipboardBridge::new(Box::new(host_clipboard)));
+            clipboard::spawn_host_watcher(bridge.clone(), clipboard_tx.clone());
+            println!("✓ Host clipboard sync enabled");
+            Some(bridge)
+        }
+        Err(e) => {
+            eprintln!("⚠️  Host clipboard unavailable, syncing between clients only: {}", e);
+            None
+        }
+    };
+
+    // Load paired devices; nothing but the pairing page works without one
+    let registry: SharedRegistry = Arc::new(
+        DeviceRegistry::load(config.devices_path()).unwrap_or_else(|e| {
+            eprintln!("⚠️  Failed to load paired devices, starting empty: {}", e);
+            DeviceRegistry::empty(config.devices_path())
+        })
+    );
+
     let local_ip = local_ip_address::local_ip()
         .unwrap_or_else(|_| "0.0.0.0".parse().unwrap());
 
+    // TLS is opt-in, with a configured certificate or a generated self-signed one
+    let tls_identity = if config.tls.enabled {
+        match tls::load_or_generate(
+            config.tls.cert.as_deref(),
+            config.tls.key.as_deref(),
+            &config.tls_dir(),
+            vec!["localhost".to_string(), local_ip.to_string()],
+        ) {
+            Ok(identity) => Some(identity),
+            Err(e) => {
+                eprintln!("❌ Failed to set up TLS: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let scheme = if tls_identity.is_some() { "https" } else { "http" };
+
     println!("\n╔════════════════════════════════════════════╗");
     println!("║    Mobile Trackpad Service Running        ║");
     println!("╚════════════════════════════════════════════╝\n");
     println!("📱 Access from your iPhone:");
-    println!("   • Local:     http://localhost:9999");
-    println!("   • Network:   http://{}:9999", local_ip);
+    println!("   • Local:     {}://localhost:{}", scheme, config.port);
+    println!("   • Network:   {}://{}:{}", scheme, local_ip, config.port);
+    if let Some(identity) = &tls_identity {
+        println!("\n🔐 TLS certificate SHA-256 fingerprint (check it on your phone):");
+        println!("   {}", identity.fingerprint);
+    }
+    if let Some(dir) = &config.deliver.dir {
+        println!("\n📂 Uploads are saved to {}", dir.display());
+    }
     println!("\n💡 Make sure your iPhone is on the same WiFi network");
     println!("🎮 Use one finger to move, two fingers to scroll");
+    auth::print_pairing_info(&registry, &format!("{}://{}:{}", scheme, local_ip, config.port));
     println!("\n⏹️  Press Ctrl+C to stop");
 
     let mouse_controller = Arc::clone(&mouse_controller);
     let clipboard_tx_ws = clipboard_tx.clone();
+    let history_ws = history.clone();
+    let registry_ws = registry.clone();
+    let file_storage_ws = file_storage.clone();
+    let config_ws = config.clone();
 
     let ws_route = warp::path("ws")
+        .and(auth::with_device(registry.clone()))
         .and(warp::ws())
-        .map(move |ws: warp::ws::Ws| {
+        .map(move |device_id: String, ws: warp::ws::Ws| {
             let mouse_controller = Arc::clone(&mouse_controller);
             let clipboard_tx = clipboard_tx_ws.clone();
-            ws.on_upgrade(move |socket| handle_websocket(socket, mouse_controller, clipboard_tx))
+            let clipboard_bridge = clipboard_bridge.clone();
+            let history = history_ws.clone();
+            let registry = registry_ws.clone();
+            let file_storage = file_storage_ws.clone();
+            let config = config_ws.clone();
+            ws.on_upgrade(move |socket| {
+                handle_websocket(
+                    socket,
+                    mouse_controller,
+                    clipboard_tx,
+                    clipboard_bridge,
+                    history,
+                    registry,
+                    file_storage,
+                    config,
+                    device_id,
+                )
+            })
         });
 
-    let html_route = warp::path::end()
-        .and(warp::fs::file("./static/index.html"));
+    // Web UI, embedded in the binary
+    let assets_route = assets::routes(config.static_dir.clone());
     
-    let clipboard_route = warp::path("clipboard.html")
-        .and(warp::fs::file("./static/clipboard.html"));
-    
-    let css_route = warp::path("style.css")
-        .and(warp::fs::file("./static/style.css"));
-    
-    let js_route = warp::path("script.js")
-        .and(warp::fs::file("./static/script.js"));
-    
-    let clipboard_js_route = warp::path("clipboard.js")
-        .and(warp::fs::file("./static/clipboard.js"));
-    
-    let files_html_route = warp::path("files.html")
-        .and(warp::fs::file("./static/files.html"));
-    
-    let files_js_route = warp::path("files.js")
-        .and(warp::fs::file("./static/files.js"));
+    // Pairing and device management routes
+    let registry_filter = {
+        let registry = registry.clone();
+        warp::any().map(move || registry.clone())
+    };
+    let tls = tls_identity.is_some();
+    let pair_route = warp::path!("pair")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and(registry_filter.clone())
+        .and(warp::any().map(move || tls))
+        .and_then(auth::handle_pair);
+    let auth_check_route = warp::path!("auth" / "check")
+        .and(warp::get())
+        .and(auth::require_device(registry.clone()))
+        .map(|| warp::reply::json(&serde_json::json!({"paired": true})));
+    let admin_devices_route = warp::path!("admin" / "devices")
+        .and(warp::get())
+        .and(auth::local_only())
+        .and(registry_filter.clone())
+        .and_then(auth::list_devices);
+    let admin_revoke_route = warp::path!("admin" / "devices" / String)
+        .and(warp::delete())
+        .and(auth::local_only())
+        .and(registry_filter.clone())
+        .and_then(auth::revoke_device);
+    let admin_pairing_route = warp::path!("admin" / "pairing")
+        .and(warp::get())
+        .and(auth::local_only())
+        .and(registry_filter)
+        .and_then(auth::pairing_info);
     
-    let static_route = warp::path("static")
-        .and(warp::fs::dir("./static"));
+    // Clipboard history routes
+    let history_filter = {
+        let history = history.clone();
+        warp::any().map(move || history.clone())
+    };
+    let history_list_route = warp::path!("clipboard" / "history")
+        .and(warp::get())
+        .and(auth::require_device(registry.clone()))
+        .and(history_filter.clone())
+        .and_then(history::list_history);
+    let history_get_route = warp::path!("clipboard" / "history" / String)
+        .and(warp::get())
+        .and(auth::require_device(registry.clone()))
+        .and(history_filter.clone())
+        .and_then(history::get_history_entry);
+    let history_pin_route = warp::path!("clipboard" / "history" / String / "pin")
+        .and(warp::post())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json())
+        .and(history_filter.clone())
+        .and_then(history::pin_history_entry);
+    let history_delete_route = warp::path!("clipboard" / "history" / String)
+        .and(warp::delete())
+        .and(auth::require_device(registry.clone()))
+        .and(history_filter)
+        .and_then(history::delete_history_entry);
     
     // File upload route
+    let quota_filter = {
+        let quota = quota.clone();
+        warp::any().map(move || quota.clone())
+    };
     let file_storage_upload = file_storage.clone();
     let clipboard_tx_upload = clipboard_tx.clone();
+    let config_upload = config.clone();
     let upload_route = warp::path("upload")
         .and(warp::post())
-        .and(warp::body::content_length_limit(1_073_741_824)) // 1 GiB body limit
-        .and(warp::multipart::form().max_length(1_073_741_824)) // 1 GiB max
+        .and(auth::require_device(registry.clone()))
+        .and(warp::query::<UploadQuery>())
+        .and(warp::body::content_length_limit(config.max_upload_bytes))
+        .and(warp::header::<u64>("content-length"))
+        .and(warp::multipart::form().max_length(config.max_upload_bytes))
         .and(warp::any().map(move || file_storage_upload.clone()))
+        .and(quota_filter.clone())
         .and(warp::any().map(move || clipboard_tx_upload.clone()))
+        .and(warp::any().map(move || config_upload.clone()))
         .and_then(handle_upload);
     
-    // File list route
-    let file_storage_list = file_storage.clone();
-    let files_route = warp::path("files")
+    // Resumable upload routes (tus.io core protocol plus creation,
+    // expiration and termination)
+    let uploads_filter = {
+        let uploads = uploads.clone();
+        warp::any().map(move || uploads.clone())
+    };
+    let config_filter = {
+        let config = config.clone();
+        warp::any().map(move || config.clone())
+    };
+    let tus_options_route = warp::path!("uploads")
+        .and(warp::options())
+        .and(auth::require_device(registry.clone()))
+        .and(config_filter.clone())
+        .and_then(uploads::handle_options);
+    let tus_create_route = warp::path!("uploads")
+        .and(warp::post())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::header::optional::<String>("tus-resumable"))
+        .and(warp::header::optional::<String>("upload-length"))
+        .and(warp::header::optional::<String>("upload-metadata"))
+        .and(uploads_filter.clone())
+        .and(quota_filter)
+        .and(config_filter.clone())
+        .and_then(uploads::handle_create);
+    let tus_head_route = warp::path!("uploads" / String)
+        .and(warp::head())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::header::optional::<String>("tus-resumable"))
+        .and(uploads_filter.clone())
+        .and_then(uploads::handle_head);
+    let file_storage_tus = file_storage.clone();
+    let clipboard_tx_tus = clipboard_tx.clone();
+    let tus_patch_route = warp::path!("uploads" / String)
+        .and(warp::patch())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::header::optional::<String>("tus-resumable"))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::header::optional::<String>("upload-offset"))
+        .and(warp::body::stream())
+        .and(uploads_filter.clone())
+        .and(warp::any().map(move || file_storage_tus.clone()))
+        .and(warp::any().map(move || clipboard_tx_tus.clone()))
+        .and(config_filter)
+        .and_then(uploads::handle_patch);
+    let tus_delete_route = warp::path!("uploads" / String)
+        .and(warp::delete())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::header::optional::<String>("tus-resumable"))
+        .and(uploads_filter)
+        .and_then(uploads::handle_delete);
+    
+    // File list and management routes
+    let file_storage_filter = {
+        let file_storage = file_storage.clone();
+        warp::any().map(move || file_storage.clone())
+    };
+    let files_route = warp::path!("files")
         .and(warp::get())
-        .map(move || {
-            let storage = file_storage_list.lock().unwrap();
-            let mut files: Vec<FileInfo> = storage.values().cloned().collect();
-            
-            // Sort by upload time, newest first
-            files.sort_by(|a, b| b.uploaded_at.cmp(&a.uploaded_at));
-            
-            // Add cache control header to prevent excessive requests
-            warp::reply::with_header(
-                warp::reply::json(&files),
-                "Cache-Control",
-                "no-cache, must-revalidate"
-            )
-        });
+        .and(auth::require_device(registry.clone()))
+        .and(file_storage_filter.clone())
+        .and_then(files::list_files);
+    let files_delete_route = warp::path!("files" / String)
+        .and(warp::delete())
+        .and(auth::require_device(registry.clone()))
+        .and(file_storage_filter.clone())
+        .and_then(files::delete_file);
+    let files_update_route = warp::path!("files" / String)
+        .and(warp::patch())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::json())
+        .and(file_storage_filter.clone())
+        .and_then(files::update_file);
+    
+    let thumbnail_route = warp::path!("files" / String / "thumbnail")
+        .and(warp::get())
+        .and(auth::require_device(registry.clone()))
+        .and(file_storage_filter.clone())
+        .and_then(thumbnails::handle_thumbnail);
     
-    // File download route
+    // Offer a file on this computer to the phones, without copying it
+    let config_share = config.clone();
+    let admin_share_route = warp::path!("admin" / "share")
+        .and(warp::post())
+        .and(auth::local_only())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::json())
+        .and(file_storage_filter)
+        .and(warp::any().map(move || config_share.clone()))
+        .and_then(files::share_host_file);
+    
+    // File download route, streamed from disk with Range support
     let file_storage_download = file_storage.clone();
-    let download_route = warp::path("download")
-        .and(warp::path::param::<String>())
+    let download_route = warp::path!("download" / String)
         .and(warp::get())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::query::<downloads::DownloadQuery>())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
         .and(warp::any().map(move || file_storage_download.clone()))
-        .and_then(|file_id: String, storage: FileStorage| async move {
-            let file_info = storage.lock().unwrap().get(&file_id).cloned();
-            
-            if let Some(info) = file_info {
-                let file_path = format!("./uploads/{}", file_id);
-                
-                // Check if file exists and get its size
-                match fs::metadata(&file_path).await {
-                    Ok(metadata) => {
-                        let file_size = metadata.len();
-                        
-                        // Read file data
-                        match fs::read(&file_path).await {
-                            Ok(file_data) => {
-                                println!("📥 Download: {} ({} bytes)", info.filename, file_size);
-                                
-                                // Return with proper headers including Content-Length
-                                Ok::<_, warp::Rejection>(
-                                    warp::reply::with_header(
-                                        warp::reply::with_header(
-                                            warp::reply::with_header(
-                                                file_data,
-                                                "Content-Type",
-                                                "application/octet-stream"
-                                            ),
-                                            "Content-Disposition",
-                                            format!("attachment; filename=\"{}\"", info.filename)
-                                        ),
-                                        "Content-Length",
-                                        file_size.to_string()
-                                    )
-                                )
-                            }
-                            Err(e) => {
-                                eprintln!("❌ Error reading file: {}", e);
-                                Err(warp::reject::not_found())
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("❌ File not found: {}", e);
-                        Err(warp::reject::not_found())
-                    }
-                }
-            } else {
-                eprintln!("❌ File ID not found in storage: {}", file_id);
-                Err(warp::reject::not_found())
-            }
-        });
+        .and_then(downloads::handle_download);
+    
+    // Several files at once, zipped on the fly
+    let file_storage_zip = file_storage.clone();
+    let zip_route = warp::path!("files" / "zip")
+        .and(warp::get())
+        .and(auth::require_device(registry.clone()))
+        .and(warp::query::<archive::ZipQuery>())
+        .and(warp::any().map(move || file_storage_zip.clone()))
+        .and_then(archive::handle_zip);
 
-    let routes = html_route
-        .or(clipboard_route)
-        .or(css_route)
-        .or(js_route)
-        .or(clipboard_js_route)
-        .or(files_html_route)
-        .or(files_js_route)
-        .or(static_route)
+    let routes = pair_route
+        .or(auth_check_route)
+        .or(admin_devices_route)
+        .or(admin_revoke_route)
+        .or(admin_pairing_route)
+        .or(admin_share_route)
         .or(upload_route)
+        .or(tus_options_route)
+        .or(tus_create_route)
+        .or(tus_head_route)
+        .or(tus_patch_route)
+        .or(tus_delete_route)
         .or(files_route)
+        .or(zip_route)
+        .or(thumbnail_route)
+        .or(files_delete_route)
+        .or(files_update_route)
         .or(download_route)
-        .or(ws_route);
+        .or(history_list_route)
+        .or(history_get_route)
+        .or(history_pin_route)
+        .or(history_delete_route)
+        .or(ws_route)
+        .or(assets_route)
+        .recover(auth::handle_rejection);
 
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 9999))
-        .await;
+    if let Some(identity) = tls_identity {
+        warp::serve(routes)
+            .tls()
+            .cert(identity.cert_pem)
+            .key(identity.key_pem)
+            .run((config.bind_address, config.port))
+            .await;
+    } else {
+        warp::serve(routes)
+            .run((config.bind_address, config.port))
+            .await;
+    }
 }
@@ -0,0 +1,368 @@
+use serde::{Deserialize, Serialize};
+
+use crate::binary;
+use crate::events::{Device, TrackpadEvent};
+
+/// Version of the WebSocket protocol this server speaks. Bumped whenever a
+/// message changes in a way old clients would misread.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest client version still understood. Clients that never say hello
+/// are taken to speak this one.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Every event type clients can send, with the device it needs.
+pub const EVENTS: &[(&str, Option<Device>)] = &[
+    ("move", Some(Device::Pointer)),
+    ("click", Some(Device::Pointer)),
+    ("scroll", Some(Device::Pointer)),
+    ("drag_start", Some(Device::Pointer)),
+    ("drag_end", Some(Device::Pointer)),
+    ("swipe", Some(Device::Pointer)),
+    ("arrow_key", Some(Device::Pointer)),
+    ("batch", Some(Device::Pointer)),
+    ("key", Some(Device::Keyboard)),
+    ("type_text", Some(Device::Keyboard)),
+    ("clipboard", None),
+];
+
+/// Ways clipboard items get around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardMode {
+    /// Items are passed between connected clients.
+    Clients,
+    /// Items are kept and replayed to clients that connect later.
+    History,
+    /// The desktop clipboard is synced both ways.
+    Host,
+}
+
+/// How a client sends its events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// JSON text frames only.
+    #[default]
+    Json,
+    /// Pointer events may also come as binary frames (see `binary`).
+    Binary,
+}
+
+/// What this server can do, sent in its `hello`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capabilities {
+    pub events: Vec<&'static str>,
+    pub devices: Vec<Device>,
+    pub clipboard: Vec<ClipboardMode>,
+    pub encodings: Vec<Encoding>,
+    /// Pointer acceleration profiles a `hello` can pick.
+    pub profiles: Vec<String>,
+}
+
+impl Capabilities {
+    /// The events that work with `devices` available and, with
+    /// `host_clipboard`, the desktop clipboard bridged.
+    pub fn new(devices: Vec<Device>, host_clipboard: bool, profiles: Vec<String>) -> Self {
+        let events = EVENTS
+            .iter()
+            .filter(|(_, device)| device.is_none_or(|device| devices.contains(&device)))
+            .map(|(name, _)| *name)
+            .collect();
+        let mut clipboard = vec![ClipboardMode::Clients, ClipboardMode::History];
+        if host_clipboard {
+            clipboard.push(ClipboardMode::Host);
+        }
+        Self {
+            events,
+            devices,
+            clipboard,
+            encodings: vec![Encoding::Json, Encoding::Binary],
+            profiles,
+        }
+    }
+
+    pub fn supports(&self, event_type: &str) -> bool {
+        self.events.contains(&event_type)
+    }
+}
+
+/// Why a client message was not acted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Not JSON, or not a JSON object.
+    InvalidJson,
+    /// No `type` string.
+    MissingType,
+    /// A `type` this server has never heard of.
+    UnknownType,
+    /// A known `type` with missing or mistyped fields.
+    InvalidEvent,
+    /// A known event needing a device this server doesn't have.
+    Unsupported,
+    /// The client's `hello` asked for a version outside what is spoken here.
+    UnsupportedVersion,
+    /// The client's `hello` asked for a pointer profile that isn't configured.
+    UnknownProfile,
+    /// A binary frame that doesn't decode, or one sent without agreeing
+    /// on the binary encoding first.
+    InvalidFrame,
+    /// The event was valid but acting on it failed.
+    EventFailed,
+}
+
+/// Messages the server sends that are about the protocol itself.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum ServerMessage {
+    /// Sent first on every connection.
+    #[serde(rename = "hello")]
+    Hello {
+        protocol_version: u32,
+        min_protocol_version: u32,
+        server: String,
+        // Boxed so the errors, which are sent far more often, stay small
+        capabilities: Box<Capabilities>,
+    },
+    /// The answer to a client `hello`, with the version, encoding and
+    /// pointer profile used from now on.
+    #[serde(rename = "ready")]
+    Ready { protocol_version: u32, encoding: Encoding, profile: String },
+    #[serde(rename = "error")]
+    Error {
+        code: ErrorCode,
+        message: String,
+        /// `type` of the message that failed, when it had one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        event: Option<String>,
+    },
+}
+
+impl ServerMessage {
+    pub fn hello(capabilities: Capabilities) -> Self {
+        ServerMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            server: format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            capabilities: Box::new(capabilities),
+        }
+    }
+
+    pub fn error(code: ErrorCode, message: impl Into<String>, event: Option<&str>) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.into(),
+            event: event.map(str::to_string),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientHello {
+    version: u32,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+/// A message from a client that passed every check.
+#[derive(Debug)]
+pub enum ClientMessage {
+    /// The client's protocol version, already known to be spoken here,
+    /// how it will send events from now on, and the pointer profile it
+    /// wants if not the default.
+    Hello { version: u32, encoding: Encoding, profile: Option<String> },
+    Event(TrackpadEvent),
+}
+
+/// Parse a client text frame, checking it against what this server can do.
+/// Errors come back ready to send.
+pub fn parse(text: &str, capabilities: &Capabilities) -> Result<ClientMessage, ServerMessage> {
+    let value: serde_json::Value = match serde_json::from_str(text) {
+        Ok(value @ serde_json::Value::Object(_)) => value,
+        Ok(_) => return Err(ServerMessage::error(ErrorCode::InvalidJson, "Expected a JSON object", None)),
+        Err(e) => return Err(ServerMessage::error(ErrorCode::InvalidJson, e.to_string(), None)),
+    };
+    let Some(event_type) = value.get("type").and_then(|t| t.as_str()).map(str::to_string) else {
+        return Err(ServerMessage::error(ErrorCode::MissingType, "Message has no type", None));
+    };
+    let invalid = |e: serde_json::Error| ServerMessage::error(ErrorCode::InvalidEvent, e.to_string(), Some(&event_type));
+
+    if event_type == "hello" {
+        let hello: ClientHello = serde_json::from_value(value).map_err(invalid)?;
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&hello.version) {
+            return Err(ServerMessage::error(
+                ErrorCode::UnsupportedVersion,
+                format!(
+                    "Protocol version {} not supported (server speaks {} to {})",
+                    hello.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                ),
+                Some(&event_type),
+            ));
+        }
+        if let Some(profile) = hello.profile.as_ref().filter(|p| !capabilities.profiles.contains(p)) {
+            return Err(ServerMessage::error(
+                ErrorCode::UnknownProfile,
+                format!("Unknown pointer profile: {}", profile),
+                Some(&event_type),
+            ));
+        }
+        return Ok(ClientMessage::Hello { version: hello.version, encoding: hello.encoding, profile: hello.profile });
+    }
+
+    if !EVENTS.iter().any(|(name, _)| *name == event_type) {
+        return Err(ServerMessage::error(
+            ErrorCode::UnknownType,
+            format!("Unknown message type: {}", event_type),
+            Some(&event_type),
+        ));
+    }
+    if !capabilities.supports(&event_type) {
+        return Err(ServerMessage::error(
+            ErrorCode::Unsupported,
+            format!("{} events are not available on this server", event_type),
+            Some(&event_type),
+        ));
+    }
+    serde_json::from_value(value).map(ClientMessage::Event).map_err(invalid)
+}
+
+/// Decode a binary frame from a client that has agreed on `encoding`.
+pub fn parse_binary(frame: &[u8], encoding: Encoding, capabilities: &Capabilities) -> Result<TrackpadEvent, ServerMessage> {
+    if encoding != Encoding::Binary {
+        return Err(ServerMessage::error(
+            ErrorCode::InvalidFrame,
+            "Binary frames need \"encoding\": \"binary\" in the hello",
+            None,
+        ));
+    }
+    let event = binary::decode(frame).map_err(|e| ServerMessage::error(ErrorCode::InvalidFrame, e.to_string(), None))?;
+    if !capabilities.supports(event.event_type()) {
+        return Err(ServerMessage::error(
+            ErrorCode::Unsupported,
+            format!("{} events are not available on this server", event.event_type()),
+            Some(event.event_type()),
+        ));
+    }
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_devices() -> Capabilities {
+        Capabilities::new(vec![Device::Pointer, Device::Keyboard], false, vec!["flat".to_string()])
+    }
+
+    fn error_code(result: Result<ClientMessage, ServerMessage>) -> ErrorCode {
+        match result {
+            Err(ServerMessage::Error { code, .. }) => code,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_listed_event_is_a_trackpad_event() {
+        for (name, _) in EVENTS {
+            // Missing fields are fine; an unknown variant would mean the list is wrong
+            match serde_json::from_value::<TrackpadEvent>(serde_json::json!({"type": name})) {
+                Ok(_) => {}
+                Err(e) => assert!(!e.to_string().contains("unknown variant"), "{}: {}", name, e),
+            }
+        }
+    }
+
+    #[test]
+    fn events_know_their_type() {
+        let events = [
+            TrackpadEvent::Move { dx: 1.0, dy: 2.0, t: None },
+            TrackpadEvent::DragEnd,
+            TrackpadEvent::TypeText { text: "hi".to_string(), delay_ms: None },
+            TrackpadEvent::Clipboard { content: "hi".to_string() },
+        ];
+        for event in events {
+            assert_eq!(serde_json::to_value(&event).unwrap()["type"], event.event_type());
+        }
+    }
+
+    #[test]
+    fn capabilities_follow_the_devices() {
+        assert_eq!(all_devices().events.len(), EVENTS.len());
+
+        let none = Capabilities::new(vec![], true, vec![]);
+        assert_eq!(none.events, ["clipboard"]);
+        assert_eq!(none.clipboard, [ClipboardMode::Clients, ClipboardMode::History, ClipboardMode::Host]);
+
+        let pointer_only = Capabilities::new(vec![Device::Pointer], false, vec![]);
+        assert!(pointer_only.supports("move"));
+        assert!(!pointer_only.supports("type_text"));
+    }
+
+    #[test]
+    fn parses_events_and_hellos() {
+        let caps = all_devices();
+        assert!(matches!(
+            parse(r#"{"type":"move","dx":1.5,"dy":-2}"#, &caps),
+            Ok(ClientMessage::Event(TrackpadEvent::Move { .. }))
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"hello","version":1}"#, &caps),
+            Ok(ClientMessage::Hello { version: 1, encoding: Encoding::Json, profile: None })
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"hello","version":1,"encoding":"binary"}"#, &caps),
+            Ok(ClientMessage::Hello { version: 1, encoding: Encoding::Binary, profile: None })
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"hello","version":1,"profile":"flat"}"#, &caps),
+            Ok(ClientMessage::Hello { profile: Some(profile), .. }) if profile == "flat"
+        ));
+    }
+
+    #[test]
+    fn explains_what_was_wrong() {
+        let caps = all_devices();
+        assert_eq!(error_code(parse("{not json", &caps)), ErrorCode::InvalidJson);
+        assert_eq!(error_code(parse("[1, 2]", &caps)), ErrorCode::InvalidJson);
+        assert_eq!(error_code(parse(r#"{"dx": 1}"#, &caps)), ErrorCode::MissingType);
+        assert_eq!(error_code(parse(r#"{"type":"teleport"}"#, &caps)), ErrorCode::UnknownType);
+        assert_eq!(error_code(parse(r#"{"type":"move","dx":"far"}"#, &caps)), ErrorCode::InvalidEvent);
+        assert_eq!(error_code(parse(r#"{"type":"hello","version":99}"#, &caps)), ErrorCode::UnsupportedVersion);
+        assert_eq!(error_code(parse(r#"{"type":"hello","version":1,"profile":"turbo"}"#, &caps)), ErrorCode::UnknownProfile);
+        assert_eq!(
+            error_code(parse(r#"{"type":"key","key":"a"}"#, &Capabilities::new(vec![], false, vec![]))),
+            ErrorCode::Unsupported
+        );
+
+        assert_eq!(error_code(parse(r#"{"type":"hello","version":1,"encoding":"xml"}"#, &caps)), ErrorCode::InvalidEvent);
+
+        let reply = serde_json::to_value(parse(r#"{"type":"move"}"#, &caps).unwrap_err()).unwrap();
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["code"], "invalid_event");
+        assert_eq!(reply["event"], "move");
+    }
+
+    #[test]
+    fn binary_frames_need_the_binary_encoding() {
+        let caps = all_devices();
+        assert!(matches!(parse_binary(&[binary::DRAG_START], Encoding::Binary, &caps), Ok(TrackpadEvent::DragStart)));
+
+        let code = |result: Result<TrackpadEvent, ServerMessage>| match result {
+            Err(ServerMessage::Error { code, .. }) => code,
+            other => panic!("expected an error, got {:?}", other),
+        };
+        assert_eq!(code(parse_binary(&[binary::DRAG_START], Encoding::Json, &caps)), ErrorCode::InvalidFrame);
+        assert_eq!(code(parse_binary(&[0xff], Encoding::Binary, &caps)), ErrorCode::InvalidFrame);
+        assert_eq!(
+            code(parse_binary(&[binary::DRAG_START], Encoding::Binary, &Capabilities::new(vec![], false, vec![]))),
+            ErrorCode::Unsupported
+        );
+    }
+}
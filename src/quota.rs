@@ -0,0 +1,336 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::config::QuotaConfig;
+use crate::files::FileStorage;
+use crate::uploads::SharedUploads;
+
+/// What uploads take up, or are about to.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub files: usize,
+    /// Bytes stored or promised to uploads in progress.
+    pub bytes: u64,
+    /// The part of `bytes` that isn't on disk yet.
+    pub pending: u64,
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            files: self.files + other.files,
+            bytes: self.bytes + other.bytes,
+            pending: self.pending + other.pending,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QuotaError {
+    TooManyFiles,
+    QuotaExceeded,
+    DiskFull,
+}
+
+impl std::fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            QuotaError::TooManyFiles => "Too many files stored, delete some first",
+            QuotaError::QuotaExceeded => "Storage quota exceeded",
+            QuotaError::DiskFull => "Not enough free disk space",
+        })
+    }
+}
+
+/// Decides whether a new upload fits, counting stored files, unfinished
+/// resumable uploads and multipart uploads in flight.
+pub struct Quota {
+    config: QuotaConfig,
+    file_storage: FileStorage,
+    uploads: SharedUploads,
+    in_flight: Mutex<Usage>,
+    /// Held from the check until the upload is accounted for, so two
+    /// uploads can't both squeeze into the same space.
+    admission: Mutex<()>,
+}
+
+pub type SharedQuota = Arc<Quota>;
+
+/// Space held for a multipart upload, released when dropped.
+pub struct Reservation {
+    quota: SharedQuota,
+    bytes: u64,
+    /// 1 while a file slot is held for the part being received.
+    files: usize,
+}
+
+impl Reservation {
+    /// A part of `bytes` is in the file list now, so stop holding its
+    /// slot and space; otherwise it would be counted twice.
+    pub fn stored(&mut self, bytes: u64) {
+        let bytes = bytes.min(self.bytes);
+        let mut in_flight = self.quota.in_flight.lock().unwrap();
+        in_flight.files -= self.files;
+        in_flight.bytes -= bytes;
+        in_flight.pending -= bytes;
+        self.files = 0;
+        self.bytes -= bytes;
+    }
+
+    /// Hold a slot for the next file of the same upload. The previous one
+    /// is stored (or failed) by now, so its slot is given back first.
+    pub fn next_file(&mut self) -> Result<(), QuotaError> {
+        let quota = self.quota.clone();
+        quota.in_flight.lock().unwrap().files -= self.files;
+        self.files = 0;
+        quota.admit(0, || {
+            quota.in_flight.lock().unwrap().files += 1;
+            self.files = 1;
+        })
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut in_flight = self.quota.in_flight.lock().unwrap();
+        in_flight.files -= self.files;
+        in_flight.bytes -= self.bytes;
+        in_flight.pending -= self.bytes;
+    }
+}
+
+impl Quota {
+    pub fn new(config: QuotaConfig, file_storage: FileStorage, uploads: SharedUploads) -> Self {
+        Self {
+            config,
+            file_storage,
+            uploads,
+            in_flight: Mutex::new(Usage::default()),
+            admission: Mutex::new(()),
+        }
+    }
+
+    /// Hold `bytes` for an upload streamed in a single request.
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Result<Reservation, QuotaError> {
+        self.admit(bytes, || {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            *in_flight = *in_flight
+                + Usage {
+                    files: 1,
+                    bytes,
+                    pending: bytes,
+                };
+            Reservation {
+                quota: self.clone(),
+                bytes,
+                files: 1,
+            }
+        })
+    }
+
+    /// Run `commit`, which must make the new upload show up in the usage,
+    /// if an upload of `bytes` fits. With `evict_oldest`, the oldest files
+    /// are deleted until it does.
+    pub fn admit<T>(&self, bytes: u64, commit: impl FnOnce() -> T) -> Result<T, QuotaError> {
+        let _admission = self.admission.lock().unwrap();
+        loop {
+            let stored = self.file_storage.usage();
+            let others = self.uploads.usage() + *self.in_flight.lock().unwrap();
+            let free = free_space(self.file_storage.uploads_dir());
+
+            let Some(error) = self.violation(stored + others, bytes, free) else {
+                return Ok(commit());
+            };
+            // Don't throw files away if that wouldn't make enough room
+            let pinned = self.file_storage.pinned_usage();
+            let evictable_bytes = stored.bytes - pinned.bytes;
+            if !self.config.evict_oldest
+                || self.violation(others + pinned, bytes, free.map(|f| f + evictable_bytes)).is_some()
+            {
+                return Err(error);
+            }
+
+            let Some(oldest) = self.file_storage.oldest_unpinned() else {
+                return Err(error);
+            };
+            match self.file_storage.remove(&oldest.id) {
+                Ok(_) => println!("🧹 Evicted {} ({}) to make room", oldest.filename, oldest.id),
+                Err(e) => {
+                    eprintln!("❌ Error saving file index: {}", e);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// The limit one more upload of `bytes` would break, if any.
+    fn violation(&self, usage: Usage, bytes: u64, free: Option<u64>) -> Option<QuotaError> {
+        if self.config.max_files.is_some_and(|max| usage.files >= max) {
+            return Some(QuotaError::TooManyFiles);
+        }
+        if self.config.max_total_bytes.is_some_and(|max| usage.bytes.saturating_add(bytes) > max) {
+            return Some(QuotaError::QuotaExceeded);
+        }
+        if free.is_some_and(|free| {
+            free.saturating_sub(usage.pending) < bytes.saturating_add(self.config.min_free_bytes)
+        }) {
+            return Some(QuotaError::DiskFull);
+        }
+        None
+    }
+}
+
+/// Bytes available to us on the filesystem holding `path` (or, before it
+/// exists, its closest existing parent).
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let existing = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out-pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // The field types vary between platforms
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::{file_info, temp_dir};
+    use crate::files::FileStore;
+    use crate::uploads::UploadSessions;
+
+    fn quota(dir: &Path, config: QuotaConfig) -> SharedQuota {
+        let file_storage = Arc::new(FileStore::empty(dir.join("files.json"), dir.join("uploads")));
+        let uploads = Arc::new(UploadSessions::empty(
+            dir.join("upload_sessions.json"),
+            dir.join("uploads/.partial"),
+            60,
+        ));
+        Arc::new(Quota::new(config, file_storage, uploads))
+    }
+
+    fn add_file(quota: &Quota, filename: &str, size: usize, uploaded_at: u64) {
+        let info = file_info(filename, size as u64, uploaded_at, u64::MAX);
+        std::fs::create_dir_all(quota.file_storage.uploads_dir()).unwrap();
+        std::fs::write(quota.file_storage.path(&info.id), vec![0; size]).unwrap();
+        quota.file_storage.insert(info).unwrap();
+    }
+
+    fn limits(max_files: Option<usize>, max_total_bytes: Option<u64>, evict_oldest: bool) -> QuotaConfig {
+        QuotaConfig {
+            max_total_bytes,
+            max_files,
+            min_free_bytes: 0,
+            evict_oldest,
+        }
+    }
+
+    #[test]
+    fn rejects_uploads_over_the_limits() {
+        let dir = temp_dir();
+        let quota = quota(&dir, limits(Some(2), Some(100), false));
+        add_file(&quota, "a", 60, 1);
+
+        assert_eq!(quota.reserve(50).err(), Some(QuotaError::QuotaExceeded));
+        let reservation = quota.reserve(40).unwrap();
+        assert_eq!(quota.reserve(0).err(), Some(QuotaError::TooManyFiles));
+
+        // Finished (or failed) uploads give their reservation back
+        drop(reservation);
+        assert!(quota.reserve(40).is_ok());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn each_file_of_an_upload_needs_a_slot() {
+        let dir = temp_dir();
+        let quota = quota(&dir, limits(Some(2), None, false));
+        add_file(&quota, "a", 10, 1);
+
+        let mut reservation = quota.reserve(100).unwrap();
+        // The first part is stored, so its slot moves to the file list
+        add_file(&quota, "b", 10, 2);
+        reservation.stored(10);
+        assert_eq!(reservation.next_file().err(), Some(QuotaError::TooManyFiles));
+        assert_eq!(*quota.in_flight.lock().unwrap(), Usage { files: 0, bytes: 90, pending: 90 });
+
+        drop(reservation);
+        assert_eq!(*quota.in_flight.lock().unwrap(), Usage::default());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn stored_parts_of_an_upload_are_counted_once() {
+        let dir = temp_dir();
+        let quota = quota(&dir, limits(None, Some(100), false));
+
+        // Two files of 40 bytes, plus a little multipart framing
+        let mut reservation = quota.reserve(85).unwrap();
+        add_file(&quota, "a", 40, 1);
+        reservation.stored(40);
+        assert_eq!(reservation.next_file(), Ok(()));
+        assert_eq!(*quota.in_flight.lock().unwrap(), Usage { files: 1, bytes: 45, pending: 45 });
+        // Another upload can have what the first one no longer needs
+        assert_eq!(quota.reserve(20).err(), Some(QuotaError::QuotaExceeded));
+        assert!(quota.reserve(15).is_ok());
+
+        drop(reservation);
+        assert_eq!(*quota.in_flight.lock().unwrap(), Usage::default());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn evicts_oldest_files_when_allowed() {
+        let dir = temp_dir();
+        let quota = quota(&dir, limits(None, Some(100), true));
+        add_file(&quota, "old", 40, 1);
+        add_file(&quota, "middle", 40, 2);
+        add_file(&quota, "new", 20, 3);
+
+        assert!(quota.admit(30, || ()).is_ok());
+        let names: Vec<_> = quota.file_storage.list().into_iter().map(|f| f.filename).collect();
+        assert_eq!(names, ["new", "middle"]);
+
+        // Nothing is evicted for an upload that could never fit
+        assert_eq!(quota.admit(150, || ()).err(), Some(QuotaError::QuotaExceeded));
+        assert_eq!(quota.file_storage.list().len(), 2);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn keeps_the_free_space_floor() {
+        let dir = temp_dir();
+        let mut config = limits(None, None, false);
+        config.min_free_bytes = 100;
+        let quota = quota(&dir, config);
+        let unfinished = Usage {
+            files: 1,
+            bytes: 500,
+            pending: 300,
+        };
+
+        assert_eq!(quota.violation(Usage::default(), 900, Some(1000)), None);
+        assert_eq!(quota.violation(Usage::default(), 901, Some(1000)), Some(QuotaError::DiskFull));
+        // Bytes promised to unfinished uploads aren't free
+        assert_eq!(quota.violation(unfinished, 700, Some(1000)), Some(QuotaError::DiskFull));
+        assert_eq!(quota.violation(unfinished, 600, Some(1000)), None);
+    }
+}
@@ -0,0 +1,196 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Read;
+use std::path::Path;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+use warp::Rejection;
+
+use crate::downloads::{self, SNIFF_LEN};
+use crate::files::{FileInfo, FileStorage};
+
+/// Longest side of a thumbnail, in pixels.
+const THUMBNAIL_SIZE: u32 = 256;
+
+const THUMBNAIL_QUALITY: u8 = 80;
+
+/// What a file turned out to be.
+#[derive(Debug, PartialEq)]
+pub struct Preview {
+    pub mime: String,
+    /// Width and height of images, as they are meant to be shown.
+    pub dimensions: Option<(u32, u32)>,
+    pub thumbnail: bool,
+}
+
+/// Image types that get a thumbnail; anything else only gets a MIME type.
+fn decodable_format(mime: &str) -> Option<ImageFormat> {
+    match mime {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Sniff the type of the file at `source` and, for images, write a JPEG
+/// thumbnail to `thumbnail`. An image that fails to decode still gets its
+/// MIME type, just no dimensions or thumbnail.
+///
+/// Decodes the whole image, so call it off the async workers.
+pub fn describe(source: &Path, thumbnail: &Path) -> std::io::Result<Preview> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(source)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    let mime = downloads::sniff_content_type(&head).to_string();
+
+    let Some(format) = decodable_format(&mime) else {
+        return Ok(Preview { mime, dimensions: None, thumbnail: false });
+    };
+    let image = match decode(source, format) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("❌ Can't decode {}: {}", source.display(), e);
+            return Ok(Preview { mime, dimensions: None, thumbnail: false });
+        }
+    };
+
+    let dimensions = Some((image.width(), image.height()));
+    if let Some(dir) = thumbnail.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let small = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).into_rgb8();
+    let out = std::io::BufWriter::new(std::fs::File::create(thumbnail)?);
+    if let Err(e) = small.write_with_encoder(JpegEncoder::new_with_quality(out, THUMBNAIL_QUALITY)) {
+        eprintln!("❌ Can't write thumbnail of {}: {}", source.display(), e);
+        std::fs::remove_file(thumbnail).ok();
+        return Ok(Preview { mime, dimensions, thumbnail: false });
+    }
+    Ok(Preview { mime, dimensions, thumbnail: true })
+}
+
+/// Decode within the default memory limits, turned upright as the EXIF
+/// orientation says; phones mostly store photos sideways.
+fn decode(path: &Path, format: ImageFormat) -> image::ImageResult<DynamicImage> {
+    let mut reader = ImageReader::open(path)?;
+    reader.set_format(format);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Describe file `id` and record the result, which clients hear about as
+/// a `file_updated`.
+fn process(file_storage: &FileStorage, info: &FileInfo) {
+    let thumbnail = file_storage.thumbnail_path(&info.id);
+    let preview = match describe(&file_storage.contents_path(info), &thumbnail) {
+        Ok(preview) => preview,
+        Err(e) => {
+            eprintln!("❌ Error reading {}: {}", info.filename, e);
+            return;
+        }
+    };
+    let updated = file_storage.update(&info.id, |f| {
+        f.mime = Some(preview.mime);
+        f.width = preview.dimensions.map(|(width, _)| width);
+        f.height = preview.dimensions.map(|(_, height)| height);
+        f.thumbnail = preview.thumbnail;
+    });
+    match updated {
+        Ok(Some(_)) => {}
+        // Deleted while we were at it
+        Ok(None) => {
+            std::fs::remove_file(&thumbnail).ok();
+        }
+        Err(e) => eprintln!("❌ Error saving file index: {}", e),
+    }
+}
+
+/// Describe a newly stored file in the background. Files that were moved
+/// out of the store on delivery are skipped.
+pub fn spawn(file_storage: FileStorage, id: String) {
+    tokio::task::spawn_blocking(move || {
+        if let Some(info) = file_storage.get(&id) {
+            process(&file_storage, &info);
+        }
+    });
+}
+
+/// Describe, one at a time, every file stored before previews were made.
+pub fn spawn_backfill(file_storage: FileStorage) {
+    tokio::task::spawn_blocking(move || {
+        for info in file_storage.list().iter().filter(|f| f.mime.is_none()) {
+            process(&file_storage, info);
+        }
+    });
+}
+
+pub async fn handle_thumbnail(id: String, storage: FileStorage) -> Result<Response<Body>, Rejection> {
+    if !storage.get(&id).is_some_and(|info| info.thumbnail) {
+        return Err(warp::reject::not_found());
+    }
+    match tokio::fs::read(storage.thumbnail_path(&id)).await {
+        Ok(data) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "image/jpeg")
+            // A file's contents never change, so neither does its thumbnail
+            .header(header::CACHE_CONTROL, "private, max-age=86400, immutable")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .body(Body::from(data))
+            .unwrap()),
+        Err(e) => {
+            eprintln!("❌ Thumbnail of {} missing: {}", id, e);
+            Err(warp::reject::not_found())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::temp_dir;
+
+    #[test]
+    fn thumbnails_images_keeping_the_aspect_ratio() {
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("photo");
+        image::RgbaImage::new(600, 300).save_with_format(&source, ImageFormat::Png).unwrap();
+
+        let thumbnail = dir.join("thumbnails").join("photo.jpg");
+        let preview = describe(&source, &thumbnail).unwrap();
+        assert_eq!(
+            preview,
+            Preview { mime: "image/png".to_string(), dimensions: Some((600, 300)), thumbnail: true }
+        );
+        let small = image::open(&thumbnail).unwrap();
+        assert_eq!((small.width(), small.height()), (256, 128));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn other_files_only_get_a_type() {
+        let dir = temp_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let notes = dir.join("notes");
+        std::fs::write(&notes, b"just text").unwrap();
+        let broken = dir.join("broken");
+        std::fs::write(&broken, b"\x89PNG\r\n\x1a\nnot really").unwrap();
+        let thumbnail = dir.join("thumbnail.jpg");
+
+        assert_eq!(
+            describe(&notes, &thumbnail).unwrap(),
+            Preview { mime: "text/plain; charset=utf-8".to_string(), dimensions: None, thumbnail: false }
+        );
+        assert_eq!(
+            describe(&broken, &thumbnail).unwrap(),
+            Preview { mime: "image/png".to_string(), dimensions: None, thumbnail: false }
+        );
+        assert!(!thumbnail.exists());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
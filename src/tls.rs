@@ -0,0 +1,98 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Validity of generated certificates; iOS rejects server certificates valid
+/// for more than 825 days.
+const CERT_VALIDITY_DAYS: i64 = 820;
+
+pub struct TlsIdentity {
+    pub cert_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+    /// SHA-256 of the DER certificate as colon-separated hex.
+    pub fingerprint: String,
+}
+
+/// Load the configured certificate and key, or the self-signed pair in
+/// `tls_dir`, generating (and persisting) it on first run.
+pub fn load_or_generate(
+    cert_path: Option<&Path>,
+    key_path: Option<&Path>,
+    tls_dir: &Path,
+    hostnames: Vec<String>,
+) -> Result<TlsIdentity, Box<dyn std::error::Error>> {
+    let (cert_path, key_path) = match (cert_path, key_path) {
+        (Some(cert), Some(key)) => (cert.to_path_buf(), key.to_path_buf()),
+        (None, None) => {
+            let cert = tls_dir.join("cert.pem");
+            let key = tls_dir.join("key.pem");
+            if !cert.exists() || !key.exists() {
+                generate_self_signed(&cert, &key, hostnames)?;
+            }
+            (cert, key)
+        }
+        _ => return Err("TLS certificate and key must be configured together".into()),
+    };
+
+    let cert_pem = std::fs::read(&cert_path)
+        .map_err(|e| format!("Failed to read {}: {}", cert_path.display(), e))?;
+    let key_pem = std::fs::read(&key_path)
+        .map_err(|e| format!("Failed to read {}: {}", key_path.display(), e))?;
+    let fingerprint = fingerprint(&cert_pem)?;
+
+    Ok(TlsIdentity {
+        cert_pem,
+        key_pem,
+        fingerprint,
+    })
+}
+
+fn generate_self_signed(
+    cert_path: &PathBuf,
+    key_path: &PathBuf,
+    hostnames: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔐 Generating self-signed certificate for {}", hostnames.join(", "));
+
+    let mut params = rcgen::CertificateParams::new(hostnames)?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "Mobile Trackpad");
+    let now = time::OffsetDateTime::now_utc();
+    params.not_before = now - time::Duration::days(1);
+    params.not_after = now + time::Duration::days(CERT_VALIDITY_DAYS);
+
+    let key_pair = rcgen::KeyPair::generate()?;
+    let cert = params.self_signed(&key_pair)?;
+
+    if let Some(dir) = cert_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(cert_path, cert.pem())?;
+    std::fs::write(key_path, key_pair.serialize_pem())?;
+    restrict_permissions(key_path)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// SHA-256 fingerprint of the first certificate in a PEM file.
+fn fingerprint(cert_pem: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let der = rustls_pemfile::certs(&mut &cert_pem[..])
+        .next()
+        .ok_or("No certificate found in PEM file")??;
+    let digest = Sha256::digest(der.as_ref());
+    Ok(digest
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
@@ -0,0 +1,856 @@
+use base64::Engine;
+use bytes::Buf;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use warp::http::{header, response, Response, StatusCode};
+use warp::hyper::Body;
+use warp::Rejection;
+
+use crate::config::Config;
+use crate::deliver;
+use crate::quota::{SharedQuota, Usage};
+use crate::thumbnails;
+use crate::files::{self, FileInfo, FileStorage};
+use crate::ClipboardItem;
+
+/// The tus protocol version spoken by the resumable upload endpoints.
+pub const TUS_VERSION: &str = "1.0.0";
+
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// Content type tus clients send chunks with.
+const CHUNK_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// A resumable upload that hasn't received all of its bytes yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub filename: String,
+    pub length: u64,
+    /// Bytes received so far; recomputed from the partial file on load.
+    #[serde(skip)]
+    pub offset: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+    /// Hex SHA-256 the client says the finished file must have.
+    #[serde(default)]
+    pub expected_sha256: Option<String>,
+    /// Set while a chunk is being written, so two requests can't interleave.
+    #[serde(skip)]
+    busy: bool,
+    /// Set once the last chunk is in, while the upload becomes a file, so
+    /// another request can't finish it a second time.
+    #[serde(skip)]
+    finalizing: bool,
+}
+
+impl UploadSession {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WriteError {
+    NotFound,
+    Locked,
+    /// All of it has arrived and it is being stored.
+    Finalizing,
+    /// The client's offset is wrong; carries the one we have.
+    OffsetMismatch(u64),
+}
+
+/// A session claimed for writing one chunk. Dropping it without
+/// [`WriteGuard::finish`], as happens when the client goes away mid-chunk,
+/// releases the session with whatever reached the disk.
+pub struct WriteGuard<'a> {
+    uploads: &'a UploadSessions,
+    session: UploadSession,
+    finished: bool,
+}
+
+impl WriteGuard<'_> {
+    /// The session as it was when claimed.
+    pub fn session(&self) -> &UploadSession {
+        &self.session
+    }
+
+    /// Release the session, now at `offset`, and push its expiry back. A
+    /// session that is complete stays claimed for `finalize`.
+    pub fn finish(mut self, offset: u64, now: u64) -> std::io::Result<Option<UploadSession>> {
+        self.finished = true;
+        self.uploads.end_write(&self.session.id, offset, now)
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let received = std::fs::metadata(self.uploads.partial_path(&self.session.id)).map_or(0, |m| m.len());
+        let mut sessions = self.uploads.sessions.lock().unwrap();
+        if let Some(session) = sessions.get_mut(&self.session.id) {
+            session.busy = false;
+            session.offset = received.min(session.length);
+        }
+    }
+}
+
+/// Unfinished resumable uploads. Their data lives in `partial_dir` under
+/// their id until complete; the sessions themselves are persisted as a
+/// JSON index so uploads can resume after a restart.
+pub struct UploadSessions {
+    index_path: PathBuf,
+    partial_dir: PathBuf,
+    ttl_secs: u64,
+    sessions: Mutex<HashMap<String, UploadSession>>,
+}
+
+pub type SharedUploads = Arc<UploadSessions>;
+
+impl UploadSessions {
+    /// No sessions; they expire `ttl_secs` after their last chunk.
+    pub fn empty(index_path: impl Into<PathBuf>, partial_dir: impl Into<PathBuf>, ttl_secs: u64) -> Self {
+        Self {
+            index_path: index_path.into(),
+            partial_dir: partial_dir.into(),
+            ttl_secs,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the sessions at `index_path`, starting empty if it doesn't exist yet.
+    pub fn load(
+        index_path: impl Into<PathBuf>,
+        partial_dir: impl Into<PathBuf>,
+        ttl_secs: u64,
+    ) -> std::io::Result<Self> {
+        let uploads = Self::empty(index_path, partial_dir, ttl_secs);
+        match std::fs::read(&uploads.index_path) {
+            Ok(data) => {
+                let sessions: Vec<UploadSession> = serde_json::from_slice(&data)?;
+                let mut map = uploads.sessions.lock().unwrap();
+                for mut session in sessions {
+                    // Whatever made it to disk is what we have
+                    let received = std::fs::metadata(uploads.partial_path(&session.id)).map_or(0, |m| m.len());
+                    session.offset = received.min(session.length);
+                    map.insert(session.id.clone(), session);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(uploads)
+    }
+
+    /// Where the bytes received so far for session `id` live.
+    pub fn partial_path(&self, id: &str) -> PathBuf {
+        self.partial_dir.join(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<UploadSession> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    /// Space promised to unfinished uploads, and how much of it is still to come.
+    pub fn usage(&self) -> Usage {
+        let sessions = self.sessions.lock().unwrap();
+        Usage {
+            files: sessions.len(),
+            bytes: sessions.values().map(|s| s.length).sum(),
+            pending: sessions.values().map(|s| s.length - s.offset).sum(),
+        }
+    }
+
+    pub fn create(
+        &self,
+        filename: &str,
+        length: u64,
+        expected_sha256: Option<String>,
+        now: u64,
+    ) -> std::io::Result<UploadSession> {
+        let session = UploadSession {
+            id: Uuid::new_v4().to_string(),
+            filename: filename.to_string(),
+            length,
+            offset: 0,
+            created_at: now,
+            expires_at: now + self.ttl_secs,
+            expected_sha256,
+            busy: false,
+            finalizing: false,
+        };
+        std::fs::create_dir_all(&self.partial_dir)?;
+        std::fs::File::create(self.partial_path(&session.id))?;
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(session.id.clone(), session.clone());
+        save(&self.index_path, &sessions)?;
+        Ok(session)
+    }
+
+    /// Claim session `id` for writing a chunk that starts at `offset`,
+    /// until the returned guard is finished or dropped.
+    pub fn begin_write(&self, id: &str, offset: u64, now: u64) -> Result<WriteGuard<'_>, WriteError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = match sessions.get_mut(id) {
+            Some(session) if now <= session.expires_at => session,
+            _ => return Err(WriteError::NotFound),
+        };
+        if session.finalizing {
+            return Err(WriteError::Finalizing);
+        }
+        if session.busy {
+            return Err(WriteError::Locked);
+        }
+        if session.offset != offset {
+            return Err(WriteError::OffsetMismatch(session.offset));
+        }
+        session.busy = true;
+        Ok(WriteGuard {
+            uploads: self,
+            session: session.clone(),
+            finished: false,
+        })
+    }
+
+    /// Record how far session `id` got and push its expiry back.
+    fn end_write(&self, id: &str, offset: u64, now: u64) -> std::io::Result<Option<UploadSession>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(id) else {
+            return Ok(None);
+        };
+        session.busy = false;
+        session.offset = offset;
+        session.finalizing = session.is_complete();
+        session.expires_at = now + self.ttl_secs;
+        let session = session.clone();
+
+        save(&self.index_path, &sessions)?;
+        Ok(Some(session))
+    }
+
+    /// Let the client complete session `id` again after `finalize` failed.
+    pub fn retry_finalize(&self, id: &str) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.finalizing = false;
+        }
+    }
+
+    /// Forget session `id` without touching its data, once it has become a file.
+    fn forget(&self, id: &str) -> std::io::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.remove(id).is_some() {
+            save(&self.index_path, &sessions)?;
+        }
+        Ok(())
+    }
+
+    /// Abandon session `id` and delete its data. Returns whether it existed.
+    pub fn remove(&self, id: &str) -> std::io::Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.remove(id).is_none() {
+            return Ok(false);
+        }
+        remove_partial(&self.partial_path(id));
+        save(&self.index_path, &sessions)?;
+        Ok(true)
+    }
+
+    /// Abandon sessions that expired before `now` and return them.
+    pub fn remove_expired(&self, now: u64) -> std::io::Result<Vec<UploadSession>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired: Vec<UploadSession> = sessions
+            .values()
+            .filter(|s| !s.busy && !s.finalizing && now > s.expires_at)
+            .cloned()
+            .collect();
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+
+        for session in &expired {
+            sessions.remove(&session.id);
+            remove_partial(&self.partial_path(&session.id));
+        }
+        save(&self.index_path, &sessions)?;
+        Ok(expired)
+    }
+
+    /// Bring the sessions and the partial directory back in line after a
+    /// restart, and return the sessions that received all of their bytes
+    /// but were never turned into files.
+    pub fn reconcile(&self, now: u64) -> std::io::Result<Vec<UploadSession>> {
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.retain(|id, session| {
+                let exists = self.partial_path(id).exists();
+                if !exists {
+                    println!("🧹 Forgetting upload of {} ({}): data missing", session.filename, id);
+                }
+                exists
+            });
+            save(&self.index_path, &sessions)?;
+        }
+
+        for session in self.remove_expired(now)? {
+            println!("🧹 Removed expired upload of {} ({})", session.filename, session.id);
+        }
+
+        match std::fs::read_dir(&self.partial_dir) {
+            Ok(entries) => {
+                let sessions = self.sessions.lock().unwrap();
+                for entry in entries {
+                    let entry = entry?;
+                    let known = entry.file_name().to_str().is_some_and(|name| sessions.contains_key(name));
+                    if !known && entry.file_type()?.is_file() {
+                        println!("🧹 Removing orphaned partial upload {}", entry.path().display());
+                        remove_partial(&entry.path());
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions.values().filter(|s| s.is_complete()).cloned().collect())
+    }
+}
+
+fn remove_partial(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("❌ Error removing {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Write via a temp file so a crash never leaves a truncated index.
+fn save(path: &Path, sessions: &HashMap<String, UploadSession>) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut sessions: Vec<&UploadSession> = sessions.values().collect();
+    sessions.sort_by_key(|s| s.created_at);
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(&sessions)?)?;
+    std::fs::rename(tmp_path, path)
+}
+
+#[derive(Debug)]
+pub enum FinalizeError {
+    /// The upload was discarded because it doesn't match the expected digest.
+    DigestMismatch { actual: String },
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for FinalizeError {
+    fn from(e: std::io::Error) -> Self {
+        FinalizeError::Io(e)
+    }
+}
+
+impl std::fmt::Display for FinalizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FinalizeError::DigestMismatch { actual } => write!(f, "SHA-256 mismatch, got {}", actual),
+            FinalizeError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Check a complete upload's digest and move it into the file store,
+/// delivering it to the host folder if one is configured. Returns the file
+/// and where it was delivered.
+///
+/// Reads the whole file, so call it off the async workers.
+pub fn finalize(
+    session: &UploadSession,
+    uploads: &UploadSessions,
+    file_storage: &FileStorage,
+    config: &Config,
+    now: u64,
+) -> Result<(FileInfo, Option<PathBuf>), FinalizeError> {
+    let sha256 = files::sha256_file(&uploads.partial_path(&session.id))?;
+    if session.expected_sha256.as_ref().is_some_and(|expected| *expected != sha256) {
+        uploads.remove(&session.id)?;
+        return Err(FinalizeError::DigestMismatch { actual: sha256 });
+    }
+
+    std::fs::create_dir_all(file_storage.uploads_dir())?;
+    std::fs::rename(uploads.partial_path(&session.id), file_storage.path(&session.id))?;
+
+    let file_info = FileInfo {
+        id: session.id.clone(),
+        filename: session.filename.clone(),
+        size: session.length,
+        uploaded_at: now,
+        expires_at: now + config.file_ttl_secs,
+        sha256: Some(sha256),
+        pinned: false,
+        host_path: None,
+        mime: None,
+        width: None,
+        height: None,
+        thumbnail: false,
+    };
+    let delivered = deliver::store_upload(file_info.clone(), file_storage, &config.deliver)?;
+    uploads.forget(&session.id)?;
+    Ok((file_info, delivered))
+}
+
+/// Key/value pairs of an `Upload-Metadata` header; values are base64.
+fn parse_metadata(header: &str) -> HashMap<String, String> {
+    header
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().filter(|k| !k.is_empty())?;
+            let value = match parts.next() {
+                Some(encoded) => {
+                    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+                    String::from_utf8(bytes).ok()?
+                }
+                None => String::new(),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+fn tus_response(status: StatusCode) -> response::Builder {
+    Response::builder()
+        .status(status)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header(header::CACHE_CONTROL, "no-store")
+}
+
+fn tus_error(status: StatusCode, message: &str) -> Response<Body> {
+    tus_response(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!({"error": message}).to_string()))
+        .unwrap()
+}
+
+/// Every request but `OPTIONS` must say which protocol version it speaks;
+/// returns the error response for those that don't.
+fn version_mismatch(tus_resumable: Option<&str>) -> Option<Response<Body>> {
+    if tus_resumable == Some(TUS_VERSION) {
+        return None;
+    }
+    let mut response = tus_error(StatusCode::PRECONDITION_FAILED, "Unsupported tus version");
+    response
+        .headers_mut()
+        .insert("Tus-Version", header::HeaderValue::from_static(TUS_VERSION));
+    Some(response)
+}
+
+fn http_date(secs: u64) -> String {
+    httpdate::fmt_http_date(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+pub async fn handle_options(config: Arc<Config>) -> Result<Response<Body>, Rejection> {
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", config.max_upload_bytes)
+        .body(Body::empty())
+        .unwrap())
+}
+
+pub async fn handle_create(
+    tus_resumable: Option<String>,
+    upload_length: Option<String>,
+    upload_metadata: Option<String>,
+    uploads: SharedUploads,
+    quota: SharedQuota,
+    config: Arc<Config>,
+) -> Result<Response<Body>, Rejection> {
+    if let Some(response) = version_mismatch(tus_resumable.as_deref()) {
+        return Ok(response);
+    }
+    let Some(length) = upload_length.and_then(|l| l.parse::<u64>().ok()) else {
+        return Ok(tus_error(StatusCode::BAD_REQUEST, "Missing or invalid Upload-Length"));
+    };
+    if length > config.max_upload_bytes {
+        return Ok(tus_error(StatusCode::PAYLOAD_TOO_LARGE, "File is too large"));
+    }
+
+    let metadata = upload_metadata.as_deref().map(parse_metadata).unwrap_or_default();
+    let filename = files::sanitize_filename(
+        metadata
+            .get("filename")
+            .or_else(|| metadata.get("name"))
+            .map_or("", |name| name.as_str()),
+    );
+    let expected_sha256 = match metadata.get("sha256").map(|digest| files::normalize_sha256(digest)) {
+        Some(None) => return Ok(tus_error(StatusCode::BAD_REQUEST, "Invalid sha256 in Upload-Metadata")),
+        Some(Some(digest)) => Some(digest),
+        None => None,
+    };
+
+    // The whole length is set aside up front, so a started upload can finish
+    let created = tokio::task::block_in_place(|| {
+        quota.admit(length, || uploads.create(&filename, length, expected_sha256, now_secs()))
+    });
+    match created {
+        Err(e) => {
+            println!("🚫 Refused upload of {}: {}", filename, e);
+            Ok(tus_error(StatusCode::INSUFFICIENT_STORAGE, &e.to_string()))
+        }
+        Ok(Ok(session)) => {
+            println!("📤 Starting upload: {} ({} KB)", session.filename, length / 1024);
+            Ok(tus_response(StatusCode::CREATED)
+                .header(header::LOCATION, format!("/uploads/{}", session.id))
+                .header("Upload-Expires", http_date(session.expires_at))
+                .body(Body::empty())
+                .unwrap())
+        }
+        Ok(Err(e)) => {
+            eprintln!("❌ Error creating upload: {}", e);
+            Ok(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create upload"))
+        }
+    }
+}
+
+pub async fn handle_head(
+    id: String,
+    tus_resumable: Option<String>,
+    uploads: SharedUploads,
+) -> Result<Response<Body>, Rejection> {
+    if let Some(response) = version_mismatch(tus_resumable.as_deref()) {
+        return Ok(response);
+    }
+    match uploads.get(&id) {
+        Some(session) if now_secs() <= session.expires_at => Ok(tus_response(StatusCode::OK)
+            .header("Upload-Offset", session.offset)
+            .header("Upload-Length", session.length)
+            .header("Upload-Expires", http_date(session.expires_at))
+            .body(Body::empty())
+            .unwrap()),
+        _ => Ok(tus_response(StatusCode::NOT_FOUND).body(Body::empty()).unwrap()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_patch<S, B>(
+    id: String,
+    tus_resumable: Option<String>,
+    content_type: Option<String>,
+    upload_offset: Option<String>,
+    body: S,
+    uploads: SharedUploads,
+    file_storage: FileStorage,
+    clipboard_tx: broadcast::Sender<ClipboardItem>,
+    config: Arc<Config>,
+) -> Result<Response<Body>, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    if let Some(response) = version_mismatch(tus_resumable.as_deref()) {
+        return Ok(response);
+    }
+    let content_type = content_type.unwrap_or_default();
+    if content_type.split(';').next().map(str::trim) != Some(CHUNK_CONTENT_TYPE) {
+        return Ok(tus_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Chunks must be application/offset+octet-stream"));
+    }
+    let Some(offset) = upload_offset.and_then(|o| o.parse::<u64>().ok()) else {
+        return Ok(tus_error(StatusCode::BAD_REQUEST, "Missing or invalid Upload-Offset"));
+    };
+
+    let guard = match uploads.begin_write(&id, offset, now_secs()) {
+        Ok(guard) => guard,
+        Err(WriteError::NotFound) => return Ok(tus_error(StatusCode::NOT_FOUND, "Upload not found")),
+        Err(WriteError::Locked) => {
+            return Ok(tus_error(StatusCode::LOCKED, "Upload is receiving another chunk"));
+        }
+        Err(WriteError::Finalizing) => {
+            return Ok(tus_error(StatusCode::CONFLICT, "Upload is already complete"));
+        }
+        Err(WriteError::OffsetMismatch(actual)) => {
+            let mut response = tus_error(StatusCode::CONFLICT, "Upload-Offset does not match");
+            response.headers_mut().insert("Upload-Offset", actual.into());
+            return Ok(response);
+        }
+    };
+
+    let remaining = guard.session().length - offset;
+    let (received, outcome) = write_chunk(&uploads.partial_path(&id), remaining, body).await;
+    let session = match guard.finish(offset + received, now_secs()) {
+        Ok(Some(session)) => session,
+        Ok(None) => return Ok(tus_error(StatusCode::NOT_FOUND, "Upload not found")),
+        Err(e) => {
+            eprintln!("❌ Error saving upload sessions: {}", e);
+            uploads.retry_finalize(&id);
+            return Ok(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload"));
+        }
+    };
+    if let Err(response) = outcome {
+        // Not finishing it now, so a later request may
+        uploads.retry_finalize(&id);
+        return Ok(response);
+    }
+
+    if !session.is_complete() {
+        println!("  Progress: {} ({} / {} KB)", session.filename, session.offset / 1024, session.length / 1024);
+        return Ok(tus_response(StatusCode::NO_CONTENT)
+            .header("Upload-Offset", session.offset)
+            .header("Upload-Expires", http_date(session.expires_at))
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let finalized = tokio::task::block_in_place(|| {
+        finalize(&session, &uploads, &file_storage, &config, now_secs())
+    });
+    match finalized {
+        Ok((file_info, delivered)) => {
+            println!("✅ Upload complete: {} ({} KB)", file_info.filename, file_info.size / 1024);
+            thumbnails::spawn(file_storage, file_info.id.clone());
+            let message = match delivered {
+                Some(path) => format!("File saved to {}", path.display()),
+                None => format!("File uploaded: {}", file_info.filename),
+            };
+            let _ = clipboard_tx.send(ClipboardItem::new(message, "System"));
+            Ok(tus_response(StatusCode::NO_CONTENT)
+                .header("Upload-Offset", session.offset)
+                .body(Body::empty())
+                .unwrap())
+        }
+        Err(FinalizeError::DigestMismatch { actual }) => {
+            eprintln!("❌ Upload of {} corrupted: SHA-256 is {}", session.filename, actual);
+            // 460 is what tus uses for checksum mismatches
+            Ok(tus_error(StatusCode::from_u16(460).unwrap(), "SHA-256 mismatch"))
+        }
+        Err(FinalizeError::Io(e)) => {
+            eprintln!("❌ Error finishing upload {}: {}", session.filename, e);
+            uploads.retry_finalize(&session.id);
+            Ok(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store file"))
+        }
+    }
+}
+
+/// Append up to `remaining` bytes of `body` to `path`.
+///
+/// Returns how many bytes were written, even on failure, since anything
+/// that reached the disk counts towards the next resume.
+async fn write_chunk<S, B>(path: &Path, remaining: u64, mut body: S) -> (u64, Result<(), Response<Body>>)
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    let mut file = match tokio::fs::OpenOptions::new().append(true).open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("❌ Error opening {}: {}", path.display(), e);
+            return (0, Err(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store chunk")));
+        }
+    };
+
+    let mut written = 0u64;
+    let mut outcome = Ok(());
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Typically the phone dropped off WiFi; it resumes from here
+                eprintln!("⚠️  Upload interrupted after {} bytes: {}", written, e);
+                outcome = Err(tus_error(StatusCode::BAD_REQUEST, "Upload interrupted"));
+                break;
+            }
+        };
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            let allowed = bytes.len().min((remaining - written) as usize);
+            if allowed == 0 {
+                outcome = Err(tus_error(StatusCode::BAD_REQUEST, "Chunk runs past Upload-Length"));
+                break;
+            }
+            if let Err(e) = file.write_all(&bytes[..allowed]).await {
+                eprintln!("❌ Error writing {}: {}", path.display(), e);
+                outcome = Err(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store chunk"));
+                break;
+            }
+            written += allowed as u64;
+            chunk.advance(allowed);
+        }
+        if outcome.is_err() {
+            break;
+        }
+    }
+
+    if let Err(e) = file.flush().await {
+        eprintln!("❌ Error writing {}: {}", path.display(), e);
+        outcome = Err(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store chunk"));
+    }
+    (written, outcome)
+}
+
+pub async fn handle_delete(
+    id: String,
+    tus_resumable: Option<String>,
+    uploads: SharedUploads,
+) -> Result<Response<Body>, Rejection> {
+    if let Some(response) = version_mismatch(tus_resumable.as_deref()) {
+        return Ok(response);
+    }
+    match uploads.remove(&id) {
+        Ok(true) => Ok(tus_response(StatusCode::NO_CONTENT).body(Body::empty()).unwrap()),
+        Ok(false) => Ok(tus_error(StatusCode::NOT_FOUND, "Upload not found")),
+        Err(e) => {
+            eprintln!("❌ Error saving upload sessions: {}", e);
+            Ok(tus_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_support::temp_dir;
+
+    fn sessions(dir: &Path) -> UploadSessions {
+        UploadSessions::load(dir.join("upload_sessions.json"), dir.join("partial"), 60).unwrap()
+    }
+
+    #[test]
+    fn parses_upload_metadata() {
+        let metadata = parse_metadata("filename cGhvdG8uanBn,is_confidential, filetype aW1hZ2UvanBlZw==");
+        assert_eq!(metadata["filename"], "photo.jpg");
+        assert_eq!(metadata["filetype"], "image/jpeg");
+        assert_eq!(metadata["is_confidential"], "");
+    }
+
+    #[test]
+    fn writes_must_start_at_the_current_offset() {
+        let dir = temp_dir();
+        let uploads = sessions(&dir);
+        let session = uploads.create("big.iso", 10, None, 100).unwrap();
+
+        assert_eq!(uploads.begin_write(&session.id, 4, 100).err(), Some(WriteError::OffsetMismatch(0)));
+        let guard = uploads.begin_write(&session.id, 0, 100).unwrap();
+        assert_eq!(uploads.begin_write(&session.id, 0, 100).err(), Some(WriteError::Locked));
+        let session = guard.finish(4, 130).unwrap().unwrap();
+        assert_eq!(session.offset, 4);
+        assert_eq!(session.expires_at, 190);
+
+        assert_eq!(uploads.begin_write(&session.id, 4, 200).err(), Some(WriteError::NotFound));
+        assert_eq!(uploads.begin_write("nope", 0, 100).err(), Some(WriteError::NotFound));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn complete_sessions_are_finalized_once() {
+        let dir = temp_dir();
+        let uploads = sessions(&dir);
+        let session = uploads.create("small.txt", 3, None, 100).unwrap();
+
+        let session = uploads.begin_write(&session.id, 0, 100).unwrap().finish(3, 100).unwrap().unwrap();
+        assert!(session.is_complete());
+        // An empty PATCH at the end can't finish it a second time
+        assert_eq!(uploads.begin_write(&session.id, 3, 100).err(), Some(WriteError::Finalizing));
+        assert!(uploads.remove_expired(1000).unwrap().is_empty());
+
+        uploads.retry_finalize(&session.id);
+        assert!(uploads.begin_write(&session.id, 3, 100).is_ok());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn dropped_writes_release_the_session() {
+        let dir = temp_dir();
+        let uploads = sessions(&dir);
+        let session = uploads.create("big.iso", 10, None, 100).unwrap();
+        let path = uploads.partial_path(&session.id);
+
+        // The client sends three bytes, then goes quiet until it's dropped
+        let body = futures::stream::iter([Ok::<_, warp::Error>(bytes::Bytes::from_static(b"abc"))])
+            .chain(futures::stream::pending());
+        let write = async {
+            let guard = uploads.begin_write(&session.id, 0, 100).unwrap();
+            let (received, _) = write_chunk(&path, 10, body).await;
+            guard.finish(received, 100)
+        };
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(50), write).await.is_err());
+
+        let guard = uploads.begin_write(&session.id, 3, 100).unwrap();
+        assert_eq!(guard.session().offset, 3);
+        drop(guard);
+        // Nor does a dropped write keep it from expiring
+        assert_eq!(uploads.remove_expired(1000).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn offsets_come_from_disk_after_restart() {
+        let dir = temp_dir();
+        let uploads = sessions(&dir);
+        let partial = uploads.create("big.iso", 10, None, 100).unwrap();
+        let complete = uploads.create("small.txt", 3, None, 100).unwrap();
+        let expired = uploads.create("old.txt", 3, None, 0).unwrap();
+        std::fs::write(uploads.partial_path(&partial.id), b"abcd").unwrap();
+        std::fs::write(uploads.partial_path(&complete.id), b"abc").unwrap();
+        let stray = uploads.partial_path(&Uuid::new_v4().to_string());
+        std::fs::write(&stray, b"?").unwrap();
+
+        let reloaded = sessions(&dir);
+        assert_eq!(reloaded.get(&partial.id).unwrap().offset, 4);
+        let finished = reloaded.reconcile(100).unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].id, complete.id);
+        assert!(reloaded.get(&expired.id).is_none());
+        assert!(!reloaded.partial_path(&expired.id).exists());
+        assert!(!stray.exists());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn finalize_checks_the_expected_digest() {
+        let dir = temp_dir();
+        let uploads = sessions(&dir);
+        let file_storage: FileStorage = Arc::new(crate::files::FileStore::empty(dir.join("files.json"), dir.join("files")));
+        // SHA-256 of "abc"
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        let good = uploads.create("good.txt", 3, Some(abc.to_string()), 100).unwrap();
+        std::fs::write(uploads.partial_path(&good.id), b"abc").unwrap();
+        let (info, delivered) = finalize(&good, &uploads, &file_storage, &Config::default(), 100).unwrap();
+        assert_eq!(delivered, None);
+        assert_eq!(info.sha256.as_deref(), Some(abc));
+        assert!(file_storage.path(&good.id).exists());
+
+        let bad = uploads.create("bad.txt", 3, Some(abc.to_string()), 100).unwrap();
+        std::fs::write(uploads.partial_path(&bad.id), b"abd").unwrap();
+        assert!(matches!(
+            finalize(&bad, &uploads, &file_storage, &Config::default(), 100),
+            Err(FinalizeError::DigestMismatch { .. })
+        ));
+        assert!(uploads.get(&bad.id).is_none());
+        assert!(!uploads.partial_path(&bad.id).exists());
+        assert!(file_storage.get(&bad.id).is_none());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,4 +1,5 @@
-const ws = new WebSocket(`ws://${window.location.host}/ws`);
+const wsProtocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
+const ws = new WebSocket(`${wsProtocol}://${window.location.host}/ws`);
 const status = document.getElementById('status');
 const sendText = document.getElementById('sendText');
 const historyList = document.getElementById('historyList');
@@ -12,8 +13,21 @@ ws.onopen = () => {
 ws.onclose = () => {
     status.textContent = '✗ Disconnected';
     status.className = 'status disconnected';
+    redirectIfNotPaired();
 };
 
+// The server refuses the WebSocket for unpaired devices; send them to pairing
+async function redirectIfNotPaired() {
+    try {
+        const response = await fetch('/auth/check');
+        if (response.status === 401) {
+            window.location.href = '/pair.html';
+        }
+    } catch (err) {
+        console.error('Failed to check pairing:', err);
+    }
+}
+
 ws.onerror = (error) => {
     console.error('WebSocket error:', error);
     status.textContent = '✗ Connection Error';
@@ -24,16 +38,17 @@ ws.onmessage = (event) => {
     try {
         const data = JSON.parse(event.data);
         if (data.type === 'clipboard_history') {
-            addHistoryItem(data.content, data.timestamp, data.source);
+            addHistoryItem(data.id, data.content, data.timestamp, data.source, data.pinned);
         }
     } catch (e) {
         console.error('Error parsing message:', e);
     }
 };
 
-function addHistoryItem(content, timestamp, source) {
+function addHistoryItem(id, content, timestamp, source, pinned = false) {
     const item = document.createElement('div');
     item.className = 'history-item';
+    item.dataset.id = id;
     
     const date = new Date(timestamp * 1000);
     const timeStr = date.toLocaleTimeString();
@@ -48,14 +63,45 @@ function addHistoryItem(content, timestamp, source) {
             <span class="history-time">${timeStr}</span>
         </div>
         <div class="history-content">${escapedContent}</div>
-        <button class="btn btn-small" data-content="${escapedContent}">📋 Copy</button>
+        <button class="btn btn-small copy-btn" data-content="${escapedContent}">📋 Copy</button>
+        <button class="btn btn-small pin-btn">${pinned ? '📌 Unpin' : '📌 Pin'}</button>
+        <button class="btn btn-small delete-btn">🗑️ Delete</button>
     `;
     
-    const copyBtn = item.querySelector('.btn');
+    const copyBtn = item.querySelector('.copy-btn');
     copyBtn.addEventListener('click', function() {
         copyHistoryItem(this);
     });
     
+    const pinBtn = item.querySelector('.pin-btn');
+    pinBtn.addEventListener('click', async () => {
+        try {
+            const response = await fetch(`/clipboard/history/${id}/pin`, {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ pinned: !pinned })
+            });
+            if (!response.ok) throw new Error(`HTTP ${response.status}`);
+            pinned = !pinned;
+            pinBtn.textContent = pinned ? '📌 Unpin' : '📌 Pin';
+        } catch (err) {
+            console.error('Failed to pin:', err);
+            showNotification('❌ Failed to update item', 'error');
+        }
+    });
+    
+    const deleteBtn = item.querySelector('.delete-btn');
+    deleteBtn.addEventListener('click', async () => {
+        try {
+            const response = await fetch(`/clipboard/history/${id}`, { method: 'DELETE' });
+            if (!response.ok) throw new Error(`HTTP ${response.status}`);
+            item.remove();
+        } catch (err) {
+            console.error('Failed to delete:', err);
+            showNotification('❌ Failed to delete item', 'error');
+        }
+    });
+    
     historyList.insertBefore(item, historyList.firstChild);
     
     while (historyList.children.length > 50) {
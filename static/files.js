@@ -1,9 +1,11 @@
-const ws = new WebSocket(`ws://${window.location.host}/ws`);
+const wsProtocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
+const ws = new WebSocket(`${wsProtocol}://${window.location.host}/ws`);
 const status = document.getElementById('status');
 const fileList = document.getElementById('fileList');
 const uploadBtn = document.getElementById('uploadBtn');
 const fileInput = document.getElementById('fileInput');
 const uploadProgress = document.getElementById('uploadProgress');
+const zipBtn = document.getElementById('zipBtn');
 
 ws.onopen = () => {
     status.textContent = '✓ Connected';
@@ -14,8 +16,21 @@ ws.onopen = () => {
 ws.onclose = () => {
     status.textContent = '✗ Disconnected';
     status.className = 'status disconnected';
+    redirectIfNotPaired();
 };
 
+// The server refuses the WebSocket for unpaired devices; send them to pairing
+async function redirectIfNotPaired() {
+    try {
+        const response = await fetch('/auth/check');
+        if (response.status === 401) {
+            window.location.href = '/pair.html';
+        }
+    } catch (err) {
+        console.error('Failed to check pairing:', err);
+    }
+}
+
 ws.onerror = (error) => {
     console.error('WebSocket error:', error);
     status.textContent = '✗ Connection Error';
@@ -25,67 +40,163 @@ ws.onerror = (error) => {
 ws.onmessage = (event) => {
     try {
         const data = JSON.parse(event.data);
-        if (data.type === 'file_uploaded') {
-            // Immediately reload file list when new file is uploaded
+        if (data.type === 'file_added') {
+            files.set(data.file.id, data.file);
+            renderFileList();
+        } else if (data.type === 'file_updated') {
+            files.set(data.file.id, data.file);
+            renderFileList();
+        } else if (data.type === 'file_shared') {
+            files.set(data.file.id, data.file);
+            renderFileList();
+            showNotification(`💻 ${data.file.filename} shared from the computer`, 'success');
+        } else if (data.type === 'file_removed') {
+            files.delete(data.id);
+            renderFileList();
+        } else if (data.type === 'files_changed') {
+            // Too many changes at once to follow; start over
             loadFileList();
-            showNotification('✅ File uploaded successfully!', 'success');
         }
     } catch (e) {
         console.error('Error parsing message:', e);
     }
 };
 
-let lastFileListHash = '';
+// Files by id, kept current by the events above
+const files = new Map();
+
+// Ids ticked for a zip download
+const selected = new Set();
 
 async function loadFileList() {
     try {
         const response = await fetch('/files', {
             cache: 'no-store' // Prevent browser caching for fresh data
         });
-        const files = await response.json();
-        
-        // Create hash to detect changes
-        const currentHash = JSON.stringify(files.map(f => f.id + f.uploaded_at));
+        const list = await response.json();
         
-        // Skip update if nothing changed
-        if (currentHash === lastFileListHash && fileList.children.length > 0) {
-            return;
-        }
-        lastFileListHash = currentHash;
-        
-        fileList.innerHTML = '';
+        files.clear();
+        list.forEach(file => files.set(file.id, file));
+        renderFileList();
+    } catch (err) {
+        console.error('Failed to load file list:', err);
+        fileList.innerHTML = '<div class="clipboard-info" style="color: rgba(255,255,255,0.7);">Failed to load files. Retrying...</div>';
+    }
+}
+
+function renderFileList() {
+    fileList.innerHTML = '';
+    
+    // Forget files that are gone
+    selected.forEach(id => { if (!files.has(id)) selected.delete(id); });
+    zipBtn.style.display = selected.size > 0 ? '' : 'none';
+    zipBtn.textContent = `📦 Download ${selected.size} selected (.zip)`;
+    
+    if (files.size === 0) {
+        fileList.innerHTML = '<div class="clipboard-info">No files available</div>';
+        return;
+    }
+    
+    // Use DocumentFragment for better performance
+    const fragment = document.createDocumentFragment();
+    
+    // Newest first, like the server sends them
+    const sorted = [...files.values()].sort((a, b) => b.uploaded_at - a.uploaded_at);
+    sorted.forEach(file => {
+        const item = document.createElement('div');
+        item.className = 'history-item';
         
-        if (files.length === 0) {
-            fileList.innerHTML = '<div class="clipboard-info">No files available</div>';
-            return;
-        }
+        const expiresDate = new Date(file.expires_at * 1000);
+        const timeLeft = Math.max(0, Math.floor((expiresDate - Date.now()) / 1000 / 60));
+        const lifetime = file.pinned ? '📌 Pinned' : `${timeLeft}min left`;
+        const dimensions = file.width && file.height ? ` • ${file.width}×${file.height}` : '';
         
-        // Use DocumentFragment for better performance
-        const fragment = document.createDocumentFragment();
+        item.innerHTML = `
+            <div class="history-header">
+                <label class="history-source">
+                    <input type="checkbox" onchange="toggleSelected('${file.id}', this.checked)" ${selected.has(file.id) ? 'checked' : ''}>
+                    ${file.host_path ? '💻' : '📎'} ${escapeHtml(file.filename)}
+                </label>
+                <span class="history-time">${formatFileSize(file.size)}${dimensions} • ${lifetime}</span>
+            </div>
+            ${file.thumbnail ? `<img class="file-thumbnail" src="/files/${file.id}/thumbnail" alt="" loading="lazy">` : ''}
+            <button class="btn btn-small" onclick="downloadFile('${file.id}')">⬇️ Download</button>
+            <a class="btn btn-small" href="/download/${file.id}?inline=true" target="_blank" rel="noopener">👁️ View</a>
+            <button class="btn btn-small" onclick="togglePin('${file.id}')">${file.pinned ? '📌 Unpin' : '📌 Pin'}</button>
+            ${file.pinned ? '' : `<button class="btn btn-small" onclick="extendFile('${file.id}')">⏳ +1h</button>`}
+            <button class="btn btn-small" onclick="renameFile('${file.id}')">✏️ Rename</button>
+            <button class="btn btn-small" onclick="deleteFile('${file.id}')">🗑️ Delete</button>
+        `;
         
-        files.forEach(file => {
-            const item = document.createElement('div');
-            item.className = 'history-item';
-            
-            const uploadDate = new Date(file.uploaded_at * 1000);
-            const expiresDate = new Date((file.uploaded_at + 3600) * 1000);
-            const timeLeft = Math.max(0, Math.floor((expiresDate - Date.now()) / 1000 / 60));
-            
-            item.innerHTML = `
-                <div class="history-header">
-                    <span class="history-source">📎 ${escapeHtml(file.filename)}</span>
-                    <span class="history-time">${formatFileSize(file.size)} • ${timeLeft}min left</span>
-                </div>
-                <button class="btn btn-small" onclick="downloadFile('${file.id}', '${escapeHtml(file.filename)}')">⬇️ Download</button>
-            `;
-            
-            fragment.appendChild(item);
+        fragment.appendChild(item);
+    });
+    
+    fileList.appendChild(fragment);
+}
+
+function toggleSelected(fileId, checked) {
+    if (checked) {
+        selected.add(fileId);
+    } else {
+        selected.delete(fileId);
+    }
+    renderFileList();
+}
+
+// The server zips the files as they're downloaded
+zipBtn.addEventListener('click', () => {
+    if (selected.size === 0) return;
+    const ids = [...selected].map(encodeURIComponent).join(',');
+    window.location.href = `/files/zip?ids=${ids}`;
+});
+
+// Changes come back over the WebSocket, so the list isn't touched here
+async function updateFile(fileId, changes) {
+    try {
+        const response = await fetch(`/files/${fileId}`, {
+            method: 'PATCH',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(changes)
         });
-        
-        fileList.appendChild(fragment);
+        if (!response.ok) {
+            const body = await response.json().catch(() => ({}));
+            throw new Error(body.error || `Request failed (${response.status})`);
+        }
     } catch (err) {
-        console.error('Failed to load file list:', err);
-        fileList.innerHTML = '<div class="clipboard-info" style="color: rgba(255,255,255,0.7);">Failed to load files. Retrying...</div>';
+        console.error('Failed to update file:', err);
+        showNotification(`❌ ${err.message}`, 'error');
+    }
+}
+
+function togglePin(fileId) {
+    const file = files.get(fileId);
+    if (file) updateFile(fileId, { pinned: !file.pinned });
+}
+
+function extendFile(fileId) {
+    updateFile(fileId, { extend_secs: 3600 });
+}
+
+function renameFile(fileId) {
+    const file = files.get(fileId);
+    if (!file) return;
+    const filename = prompt('New name', file.filename);
+    if (filename && filename !== file.filename) {
+        updateFile(fileId, { filename });
+    }
+}
+
+async function deleteFile(fileId) {
+    const file = files.get(fileId);
+    if (!file || !confirm(`Delete ${file.filename}?`)) return;
+    try {
+        const response = await fetch(`/files/${fileId}`, { method: 'DELETE' });
+        if (!response.ok && response.status !== 404) {
+            throw new Error(`Delete failed (${response.status})`);
+        }
+    } catch (err) {
+        console.error('Failed to delete file:', err);
+        showNotification(`❌ ${err.message}`, 'error');
     }
 }
 
@@ -110,9 +221,6 @@ async function uploadFiles() {
     
     for (let i = 0; i < files.length; i++) {
         const file = files[i];
-        const formData = new FormData();
-        formData.append('file', file);
-        
         try {
             // Create progress display
             const progressDiv = document.createElement('div');
@@ -128,57 +236,26 @@ async function uploadFiles() {
             `;
             uploadProgress.appendChild(progressDiv);
             
-            // Use XMLHttpRequest for progress tracking
-            await new Promise((resolve, reject) => {
-                const xhr = new XMLHttpRequest();
-                
-                xhr.upload.addEventListener('progress', (e) => {
-                    if (e.lengthComputable) {
-                        const percent = Math.round((e.loaded / e.total) * 100);
-                        const progressText = document.getElementById(`progress-${i}`);
-                        const progressBar = document.getElementById(`bar-${i}`);
-                        if (progressText) {
-                            progressText.textContent = `${percent}% (${formatFileSize(e.loaded)} / ${formatFileSize(e.total)})`;
-                        }
-                        if (progressBar) {
-                            progressBar.style.width = percent + '%';
-                        }
-                    }
-                });
-                
-                xhr.addEventListener('load', () => {
-                    if (xhr.status === 200) {
-                        try {
-                            const result = JSON.parse(xhr.responseText);
-                            // Notify all clients about new file
-                            ws.send(JSON.stringify({
-                                type: 'file_notification',
-                                file_id: result.id
-                            }));
-                            const progressText = document.getElementById(`progress-${i}`);
-                            if (progressText) {
-                                progressText.textContent = '✓ Complete';
-                                progressText.style.color = '#4CAF50';
-                            }
-                            resolve();
-                        } catch (e) {
-                            reject(new Error('Invalid response'));
-                        }
-                    } else {
-                        reject(new Error('Upload failed'));
-                    }
-                });
-                
-                xhr.addEventListener('error', () => reject(new Error('Network error')));
-                xhr.addEventListener('abort', () => reject(new Error('Upload cancelled')));
-                
-                xhr.open('POST', '/upload');
-                xhr.send(formData);
+            await uploadResumable(file, (loaded, total) => {
+                const percent = total > 0 ? Math.round((loaded / total) * 100) : 100;
+                const progressText = document.getElementById(`progress-${i}`);
+                const progressBar = document.getElementById(`bar-${i}`);
+                if (progressText) {
+                    progressText.textContent = `${percent}% (${formatFileSize(loaded)} / ${formatFileSize(total)})`;
+                }
+                if (progressBar) {
+                    progressBar.style.width = percent + '%';
+                }
             });
             
+            const progressText = document.getElementById(`progress-${i}`);
+            if (progressText) {
+                progressText.textContent = '✓ Complete';
+                progressText.style.color = '#4CAF50';
+            }
         } catch (err) {
             console.error('Upload error:', err);
-            showNotification(`❌ Failed to upload ${file.name}`, 'error');
+            showNotification(`❌ Failed to upload ${file.name}: ${err.message}`, 'error');
             const progressText = document.getElementById(`progress-${i}`);
             if (progressText) {
                 progressText.textContent = '✗ Failed';
@@ -193,13 +270,117 @@ async function uploadFiles() {
     }, 3000);
     
     fileInput.value = '';
-    loadFileList();
 }
 
-function downloadFile(fileId, filename) {
+// Resumable uploads (tus protocol): the file goes up in chunks, and after a
+// dropped connection we ask the server how much arrived and carry on from there
+const TUS_VERSION = '1.0.0';
+const CHUNK_SIZE = 8 * 1024 * 1024;
+const MAX_RETRIES = 5;
+
+// Remember upload URLs so even a page reload can resume
+function uploadKey(file) {
+    return `upload:${file.name}:${file.size}:${file.lastModified}`;
+}
+
+function encodeMetadata(value) {
+    const bytes = new TextEncoder().encode(value);
+    return btoa(String.fromCharCode(...bytes));
+}
+
+async function createUpload(file) {
+    const response = await fetch('/uploads', {
+        method: 'POST',
+        headers: {
+            'Tus-Resumable': TUS_VERSION,
+            'Upload-Length': String(file.size),
+            'Upload-Metadata': `filename ${encodeMetadata(file.name)}`
+        }
+    });
+    if (response.status !== 201) {
+        // e.g. 507 when the storage quota is used up
+        const body = await response.json().catch(() => ({}));
+        throw new Error(body.error || `Failed to start upload (${response.status})`);
+    }
+    return response.headers.get('Location');
+}
+
+// Bytes the server has, or null if it doesn't know the upload (any more)
+async function fetchOffset(url) {
+    const response = await fetch(url, {
+        method: 'HEAD',
+        headers: { 'Tus-Resumable': TUS_VERSION },
+        cache: 'no-store'
+    });
+    if (!response.ok) return null;
+    return parseInt(response.headers.get('Upload-Offset'), 10);
+}
+
+function sendChunk(url, offset, chunk, onProgress) {
+    return new Promise((resolve, reject) => {
+        const xhr = new XMLHttpRequest();
+        
+        xhr.upload.addEventListener('progress', (e) => onProgress(e.loaded));
+        xhr.addEventListener('load', () => {
+            if (xhr.status === 204) {
+                resolve(parseInt(xhr.getResponseHeader('Upload-Offset'), 10));
+            } else {
+                reject(new Error(`Chunk rejected (${xhr.status})`));
+            }
+        });
+        xhr.addEventListener('error', () => reject(new Error('Network error')));
+        xhr.addEventListener('abort', () => reject(new Error('Upload cancelled')));
+        
+        xhr.open('PATCH', url);
+        xhr.setRequestHeader('Tus-Resumable', TUS_VERSION);
+        xhr.setRequestHeader('Upload-Offset', String(offset));
+        xhr.setRequestHeader('Content-Type', 'application/offset+octet-stream');
+        xhr.send(chunk);
+    });
+}
+
+async function uploadResumable(file, onProgress) {
+    const key = uploadKey(file);
+    let url = localStorage.getItem(key);
+    let offset = url ? await fetchOffset(url).catch(() => null) : null;
+    if (offset === null) {
+        url = await createUpload(file);
+        localStorage.setItem(key, url);
+        offset = 0;
+    }
+    
+    let failures = 0;
+    do {
+        const start = offset;
+        try {
+            offset = await sendChunk(url, start, file.slice(start, start + CHUNK_SIZE), (loaded) => {
+                onProgress(start + loaded, file.size);
+            });
+            failures = 0;
+        } catch (err) {
+            if (++failures > MAX_RETRIES) throw err;
+            console.warn(`Upload of ${file.name} interrupted, retrying:`, err);
+            await new Promise(resolve => setTimeout(resolve, 1000 * failures));
+            
+            const serverOffset = await fetchOffset(url).catch(() => offset);
+            if (serverOffset === null) {
+                localStorage.removeItem(key);
+                throw new Error('Upload expired on the server');
+            }
+            offset = serverOffset;
+        }
+    } while (offset < file.size);
+    
+    localStorage.removeItem(key);
+}
+
+// Looked up by id: filenames can hold quotes that would break the onclick
+function downloadFile(fileId) {
+    const file = files.get(fileId);
+    if (!file) return;
     const link = document.createElement('a');
     link.href = `/download/${fileId}`;
-    link.download = filename;
+    link.download = file.filename;
     link.click();
 }
 
@@ -238,5 +419,5 @@ uploadBtn.addEventListener('click', () => {
 
 fileInput.addEventListener('change', uploadFiles);
 
-// Auto-refresh file list every 60 seconds (reduced since we have WebSocket updates)
-setInterval(loadFileList, 60000);
+// Changes arrive over the WebSocket; this only keeps the time left current
+setInterval(renderFileList, 60000);
@@ -0,0 +1,50 @@
+const status = document.getElementById('status');
+const pinInput = document.getElementById('pinInput');
+const nameInput = document.getElementById('nameInput');
+const pairBtn = document.getElementById('pairBtn');
+
+// The QR code printed by the server links here with the PIN filled in
+const params = new URLSearchParams(window.location.search);
+if (params.get('pin')) {
+    pinInput.value = params.get('pin');
+}
+
+async function pair() {
+    const pin = pinInput.value.trim();
+    if (!pin) {
+        status.textContent = '⚠️ Enter the PIN first';
+        return;
+    }
+    
+    try {
+        const response = await fetch('/pair', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ pin, name: nameInput.value.trim() })
+        });
+        const result = await response.json();
+        
+        if (response.ok) {
+            // The server sets the token cookie; everything else just works now
+            status.textContent = '✓ Paired';
+            status.className = 'status connected';
+            window.location.href = '/';
+        } else {
+            status.textContent = `✗ ${result.error || 'Pairing failed'}`;
+            status.className = 'status disconnected';
+        }
+    } catch (err) {
+        console.error('Pairing error:', err);
+        status.textContent = '✗ Connection Error';
+        status.className = 'status disconnected';
+    }
+}
+
+pairBtn.addEventListener('click', pair);
+
+pinInput.addEventListener('keydown', (e) => {
+    if (e.key === 'Enter') {
+        e.preventDefault();
+        pair();
+    }
+});
@@ -1,4 +1,5 @@
-const ws = new WebSocket(`ws://${window.location.host}/ws`);
+const wsProtocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
+const ws = new WebSocket(`${wsProtocol}://${window.location.host}/ws`);
 const trackpad = document.getElementById('trackpad');
 const status = document.getElementById('status');
 const leftBtn = document.getElementById('leftBtn');
@@ -25,25 +26,103 @@ const MOVE_THRESHOLD = 10; // pixels
 const SWIPE_THRESHOLD = 100; // pixels for horizontal swipe
 const SWIPE_ANGLE_THRESHOLD = 0.5; // ratio of dy/dx to determine if mostly horizontal
 
+// Protocol version this page speaks; the server says which it accepts
+const PROTOCOL_VERSION = 1;
+
+// Event types the server can act on, from its hello
+let serverEvents = null;
+
+// Set once the server agrees; pointer events then go as binary frames
+let binaryFrames = false;
+
 ws.onopen = () => {
     status.textContent = '✓ Connected';
     status.className = 'status connected';
+    ws.send(JSON.stringify({ type: 'hello', version: PROTOCOL_VERSION, encoding: 'binary' }));
 };
 
 ws.onclose = () => {
     status.textContent = '✗ Disconnected';
     status.className = 'status disconnected';
+    redirectIfNotPaired();
 };
 
+// The server refuses the WebSocket for unpaired devices; send them to pairing
+async function redirectIfNotPaired() {
+    try {
+        const response = await fetch('/auth/check');
+        if (response.status === 401) {
+            window.location.href = '/pair.html';
+        }
+    } catch (err) {
+        console.error('Failed to check pairing:', err);
+    }
+}
+
 ws.onerror = (error) => {
     console.error('WebSocket error:', error);
     status.textContent = '✗ Connection Error';
     status.className = 'status disconnected';
 };
 
+// Anything queued goes first, so a click lands where the pointer was moved
 function sendEvent(event) {
+    flushMotion();
+    sendFrame(event);
+}
+
+function sendFrame(event) {
     if (ws.readyState === WebSocket.OPEN) {
-        ws.send(JSON.stringify(event));
+        const frame = binaryFrames ? encodeBinary(event) : null;
+        ws.send(frame || JSON.stringify(event));
+    }
+}
+
+// Motion waiting for the next frame; the server adds up the fractions
+let pendingMotion = [];
+
+function queueMotion(kind, dx, dy) {
+    if (pendingMotion.length === 0) {
+        requestAnimationFrame(flushMotion);
+    }
+    pendingMotion.push({ kind, dx, dy, t: performance.now() });
+}
+
+// Everything since the last frame goes as one batch, applied by the server at once
+function flushMotion() {
+    const events = pendingMotion;
+    pendingMotion = [];
+    if (events.length > 1 && serverEvents && serverEvents.includes('batch')) {
+        sendFrame({ type: 'batch', events });
+    } else {
+        // Binary frames leave the timestamp out; the server then times moves itself
+        events.forEach(({ kind, dx, dy, t }) => sendFrame(kind === 'move' ? { type: kind, dx, dy, t } : { type: kind, dx, dy }));
+    }
+}
+
+// Binary frames: an opcode byte, then little-endian fields (see src/binary.rs)
+const BINARY_BUTTONS = ['left', 'right', 'middle'];
+
+function encodeBinary(event) {
+    switch (event.type) {
+        case 'move':
+        case 'scroll': {
+            const view = new DataView(new ArrayBuffer(9));
+            view.setUint8(0, event.type === 'move' ? 0x01 : 0x02);
+            view.setFloat32(1, event.dx, true);
+            view.setFloat32(5, event.dy, true);
+            return view.buffer;
+        }
+        case 'click': {
+            const button = BINARY_BUTTONS.indexOf(event.button);
+            return button < 0 ? null : new Uint8Array([0x03, button]).buffer;
+        }
+        case 'drag_start':
+            return new Uint8Array([0x04]).buffer;
+        case 'drag_end':
+            return new Uint8Array([0x05]).buffer;
+        default:
+            return null;
     }
 }
 
@@ -99,10 +178,12 @@ trackpad.addEventListener('touchmove', (e) => {
             const dx = (currentX - lastX) * sensitivity;
             const dy = (currentY - lastY) * sensitivity;
             
-            // Check if moved beyond threshold
+            // Check if moved beyond threshold; after that, every bit counts
             if (Math.abs(dx) > 1 || Math.abs(dy) > 1) {
                 hasMoved = true;
-                sendEvent({ type: 'move', dx, dy });
+            }
+            if (hasMoved) {
+                queueMotion('move', dx, dy);
             }
         }
         
@@ -122,7 +203,7 @@ trackpad.addEventListener('touchmove', (e) => {
             twoFingerTotalDx += dx;
             twoFingerTotalDy += dy;
             
-            sendEvent({ type: 'scroll', dx: dx * 2, dy: dy * 2 });
+            queueMotion('scroll', dx * 2, dy * 2);
         }
         
         lastX = midX;
@@ -191,7 +272,7 @@ trackpad.addEventListener('mousemove', (e) => {
     if (lastX !== null && lastY !== null) {
         const dx = (e.clientX - lastX) * sensitivity;
         const dy = (e.clientY - lastY) * sensitivity;
-        sendEvent({ type: 'move', dx, dy });
+        queueMotion('move', dx, dy);
     }
     
     lastX = e.clientX;
@@ -241,6 +322,60 @@ arrowRight.addEventListener('click', (e) => {
     sendEvent({ type: 'arrow_key', key: 'right' });
 });
 
+// Special key buttons (Esc, Tab, Backspace, Enter)
+document.querySelectorAll('.key-btn').forEach((btn) => {
+    btn.addEventListener('click', (e) => {
+        e.preventDefault();
+        sendEvent({ type: 'key', key: btn.dataset.key, action: 'tap' });
+    });
+});
+
+// Type text on the computer
+const typeInput = document.getElementById('typeInput');
+const typeBtn = document.getElementById('typeBtn');
+
+function typeText() {
+    const text = typeInput.value;
+    if (!text) return;
+    sendEvent({ type: 'type_text', text });
+    typeInput.value = '';
+}
+
+typeBtn.addEventListener('click', (e) => {
+    e.preventDefault();
+    typeText();
+});
+
+typeInput.addEventListener('keydown', (e) => {
+    if (e.key === 'Enter') {
+        e.preventDefault();
+        typeText();
+    }
+});
+
+ws.onmessage = (event) => {
+    try {
+        const data = JSON.parse(event.data);
+        if (data.type === 'hello') {
+            serverEvents = data.capabilities.events;
+            if (!serverEvents.includes('move')) {
+                status.textContent = '⚠️ Connected, but input is unavailable on the computer';
+            }
+        } else if (data.type === 'ready') {
+            binaryFrames = data.encoding === 'binary';
+        } else if (data.type === 'error') {
+            console.warn(`Server rejected ${data.event || 'message'} (${data.code}):`, data.message);
+        } else if (data.type === 'file_shared') {
+            // Offered from the computer; fetching it is up to the user
+            if (confirm(`Download ${data.file.filename} from the computer?`)) {
+                window.location.href = data.url;
+            }
+        }
+    } catch (e) {
+        console.error('Error parsing message:', e);
+    }
+};
+
 // Prevent default touch behaviors
 document.addEventListener('touchmove', (e) => {
     e.preventDefault();